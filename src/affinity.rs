@@ -0,0 +1,219 @@
+use crate::protocol::{CachePartitionsResponse, TopologyVersion};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// the java hashCode of a primitive key, which is what the server's affinity function hashes
+pub fn hash_code(key: &Value) -> Option<i32> {
+    let hash = match key {
+        Value::Null => return None,
+        Value::I8(v) => *v as i32,
+        Value::I16(v) => *v as i32,
+        Value::I32(v) => *v,
+        Value::I64(v) => long_hash_code(*v),
+        // java canonicalizes NaN before hashing
+        Value::F32(v) if v.is_nan() => 0x7fc00000,
+        Value::F32(v) => v.to_bits() as i32,
+        Value::F64(v) if v.is_nan() => long_hash_code(0x7ff8000000000000),
+        Value::F64(v) => long_hash_code(v.to_bits() as i64),
+        Value::Char(v) => {
+            // chars outside the BMP can't be sent as keys
+            let mut units = [0u16; 2];
+            match v.encode_utf16(&mut units) {
+                [unit] => *unit as i32,
+                _ => return None,
+            }
+        }
+        Value::Bool(true) => 1231,
+        Value::Bool(false) => 1237,
+        Value::String(v) => crate::protocol::cache_id(v),
+        // hashed as java objects whose hashCode the client doesn't replicate
+        Value::Uuid(_)
+        | Value::Date(_)
+        | Value::Timestamp { .. }
+        | Value::Time(_)
+        | Value::Decimal { .. }
+        | Value::Bytes(_) => return None,
+    };
+    Some(hash)
+}
+
+fn long_hash_code(v: i64) -> i32 {
+    (v ^ ((v as u64) >> 32) as i64) as i32
+}
+
+// the partition RendezvousAffinityFunction assigns to the key
+pub fn partition(key: &Value, partitions: usize) -> Option<usize> {
+    if partitions == 0 {
+        return None;
+    }
+    let hash = hash_code(key)?;
+    if partitions.is_power_of_two() {
+        let mask = partitions - 1;
+        Some((hash ^ ((hash as u32) >> 16) as i32) as usize & mask)
+    } else {
+        Some((hash % partitions as i32).unsigned_abs() as usize)
+    }
+}
+
+// caches never have more partitions than this, larger ids on the wire are invalid
+const MAX_PARTITIONS: usize = 65000;
+
+// primary node of every partition, per cache
+#[derive(Default)]
+pub(crate) struct PartitionMap {
+    // the topology the partitions below were fetched at, and when it was last confirmed
+    topology_version: Option<TopologyVersion>,
+    fetched_at: Option<Instant>,
+    // None for caches whose partitions can't be computed on the client, e.g. a custom
+    // affinity function. a partition with no owner in the response maps to None
+    caches: HashMap<i32, Option<Vec<Option<u128>>>>,
+}
+
+impl PartitionMap {
+    pub(crate) fn contains(&self, cache_id: i32) -> bool {
+        self.caches.contains_key(&cache_id)
+    }
+
+    pub(crate) fn topology_version(&self) -> Option<TopologyVersion> {
+        self.topology_version
+    }
+
+    // whether the topology version hasn't been confirmed by the server for max_age
+    pub(crate) fn is_stale(&self, max_age: Duration) -> bool {
+        self.fetched_at.is_none_or(|fetched_at| fetched_at.elapsed() >= max_age)
+    }
+
+    // a newer topology drops the partitions of every other cache, they are fetched again on use.
+    // a response from an older topology is ignored
+    pub(crate) fn update(&mut self, response: &CachePartitionsResponse) {
+        let topology_version = response.affinity_topology_version;
+        match self.topology_version {
+            Some(current) if current > topology_version => return,
+            Some(current) if current == topology_version => {}
+            _ => {
+                self.caches.clear();
+                self.topology_version = Some(topology_version);
+            }
+        }
+        self.fetched_at = Some(Instant::now());
+        for mapping in &response.mappings {
+            if !mapping.applicable {
+                for cache_id in &mapping.cache_ids {
+                    self.caches.insert(*cache_id, None);
+                }
+                continue;
+            }
+            let owned_partitions = || {
+                mapping.node_partitions.iter().flat_map(|node| {
+                    node.partitions
+                        .iter()
+                        .filter_map(|partition| usize::try_from(*partition).ok())
+                        .filter(|partition| *partition < MAX_PARTITIONS)
+                        .map(|partition| (partition, node.node_id))
+                })
+            };
+            let partition_count = owned_partitions()
+                .map(|(partition, _)| partition + 1)
+                .max()
+                .unwrap_or(0);
+            let mut owners = vec![None; partition_count];
+            for (partition, node_id) in owned_partitions() {
+                owners[partition] = Some(node_id);
+            }
+            for cache_id in &mapping.cache_ids {
+                self.caches.insert(*cache_id, Some(owners.clone()));
+            }
+        }
+    }
+
+    pub(crate) fn primary_node(&self, cache_id: i32, key: &Value) -> Option<u128> {
+        let owners = self.caches.get(&cache_id)?.as_ref()?;
+        *owners.get(partition(key, owners.len())?)?
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.topology_version = None;
+        self.fetched_at = None;
+        self.caches.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{NodePartitions, PartitionMapping};
+
+    fn cache_partitions(major: i64, cache_id: i32, node_id: u128) -> CachePartitionsResponse {
+        CachePartitionsResponse {
+            affinity_topology_version: TopologyVersion { major, minor: 0 },
+            mappings: vec![PartitionMapping {
+                applicable: true,
+                cache_ids: vec![cache_id],
+                node_partitions: vec![NodePartitions {
+                    node_id,
+                    partitions: (0..1024).collect(),
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_partition_map_topology_change() {
+        let mut partition_map = PartitionMap::default();
+        partition_map.update(&cache_partitions(1, 1, 10));
+        partition_map.update(&cache_partitions(1, 2, 10));
+        assert!(partition_map.contains(1) && partition_map.contains(2));
+        assert!(!partition_map.is_stale(Duration::from_secs(60)));
+        assert!(partition_map.is_stale(Duration::ZERO));
+
+        partition_map.update(&cache_partitions(2, 1, 20));
+        assert_eq!(
+            partition_map.topology_version(),
+            Some(TopologyVersion { major: 2, minor: 0 })
+        );
+        assert_eq!(partition_map.primary_node(1, &Value::I32(1)), Some(20));
+        assert!(!partition_map.contains(2));
+
+        partition_map.update(&cache_partitions(1, 2, 10));
+        assert!(!partition_map.contains(2));
+    }
+
+    #[test]
+    fn test_partition_map_ignores_invalid_partitions() {
+        let mut response = cache_partitions(1, 1, 10);
+        response.mappings[0].node_partitions[0].partitions = vec![0, -1, 1, i32::MAX];
+        response.mappings.push(PartitionMapping {
+            applicable: false,
+            cache_ids: vec![2],
+            node_partitions: vec![],
+        });
+
+        let mut partition_map = PartitionMap::default();
+        partition_map.update(&response);
+        assert_eq!(partition_map.primary_node(1, &Value::I32(1)), Some(10));
+        assert!(partition_map.contains(2));
+        assert_eq!(partition_map.primary_node(2, &Value::I32(1)), None);
+    }
+
+    #[test]
+    fn test_hash_code() {
+        assert_eq!(hash_code(&Value::String("hello".to_string())), Some(99162322));
+        assert_eq!(hash_code(&Value::I64((1 << 32) + 5)), Some(4));
+        assert_eq!(hash_code(&Value::F64(1.5)), Some(1073217536));
+        assert_eq!(hash_code(&Value::Bool(true)), Some(1231));
+        assert_eq!(hash_code(&Value::Char('a')), Some(97));
+        assert_eq!(hash_code(&Value::Char('\u{1f600}')), None);
+        assert_eq!(hash_code(&Value::Null), None);
+    }
+
+    #[test]
+    fn test_partition() {
+        assert_eq!(partition(&Value::I32(1), 1024), Some(1));
+        assert_eq!(partition(&Value::I32(-1), 1024), Some(0));
+        assert_eq!(partition(&Value::I32(123456789), 1024), Some(590));
+        assert_eq!(partition(&Value::String("hello".to_string()), 1024), Some(315));
+        assert_eq!(partition(&Value::String("hello".to_string()), 1000), Some(322));
+        assert_eq!(partition(&Value::I32(-7), 1000), Some(7));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::op_const;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ServerStatus {
+    Success,
+    Failed,
+    InvalidOpCode,
+    CacheDoesNotExist,
+    CacheExists,
+    TooManyCursors,
+    ResourceDoesNotExist,
+    SecurityViolation,
+    TxLimitExceeded,
+    TxNotFound,
+    TooManyComputeTasks,
+    AuthFailed,
+    Unknown(i32),
+}
+
+impl From<i32> for ServerStatus {
+    fn from(code: i32) -> Self {
+        match code {
+            op_const::STATUS_SUCCESS => ServerStatus::Success,
+            op_const::STATUS_FAILED => ServerStatus::Failed,
+            op_const::STATUS_INVALID_OP_CODE => ServerStatus::InvalidOpCode,
+            op_const::STATUS_CACHE_DOES_NOT_EXIST => ServerStatus::CacheDoesNotExist,
+            op_const::STATUS_CACHE_EXISTS => ServerStatus::CacheExists,
+            op_const::STATUS_TOO_MANY_CURSORS => ServerStatus::TooManyCursors,
+            op_const::STATUS_RESOURCE_DOES_NOT_EXIST => ServerStatus::ResourceDoesNotExist,
+            op_const::STATUS_SECURITY_VIOLATION => ServerStatus::SecurityViolation,
+            op_const::STATUS_TX_LIMIT_EXCEEDED => ServerStatus::TxLimitExceeded,
+            op_const::STATUS_TX_NOT_FOUND => ServerStatus::TxNotFound,
+            op_const::STATUS_TOO_MANY_COMPUTE_TASKS => ServerStatus::TooManyComputeTasks,
+            op_const::STATUS_AUTH_FAILED => ServerStatus::AuthFailed,
+            other => ServerStatus::Unknown(other),
+        }
+    }
+}
+
+impl ServerStatus {
+    pub fn code(&self) -> i32 {
+        match self {
+            ServerStatus::Success => op_const::STATUS_SUCCESS,
+            ServerStatus::Failed => op_const::STATUS_FAILED,
+            ServerStatus::InvalidOpCode => op_const::STATUS_INVALID_OP_CODE,
+            ServerStatus::CacheDoesNotExist => op_const::STATUS_CACHE_DOES_NOT_EXIST,
+            ServerStatus::CacheExists => op_const::STATUS_CACHE_EXISTS,
+            ServerStatus::TooManyCursors => op_const::STATUS_TOO_MANY_CURSORS,
+            ServerStatus::ResourceDoesNotExist => op_const::STATUS_RESOURCE_DOES_NOT_EXIST,
+            ServerStatus::SecurityViolation => op_const::STATUS_SECURITY_VIOLATION,
+            ServerStatus::TxLimitExceeded => op_const::STATUS_TX_LIMIT_EXCEEDED,
+            ServerStatus::TxNotFound => op_const::STATUS_TX_NOT_FOUND,
+            ServerStatus::TooManyComputeTasks => op_const::STATUS_TOO_MANY_COMPUTE_TASKS,
+            ServerStatus::AuthFailed => op_const::STATUS_AUTH_FAILED,
+            ServerStatus::Unknown(code) => *code,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IgniteError {
+    Io(io::Error),
+    Server {
+        status: ServerStatus,
+        message: String,
+    },
+    NotHandshaked,
+    HandshakeFailed {
+        major_version: i16,
+        minor_version: i16,
+        patch_version: i16,
+        message: String,
+    },
+    RequestIdMismatch {
+        expected: i64,
+        actual: i64,
+    },
+    ResponseTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    TimedOut,
+}
+
+impl fmt::Display for IgniteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IgniteError::Io(e) => write!(f, "{}", e),
+            IgniteError::Server { status, message } => {
+                write!(f, "Error: {:?} ({}): {}", status, status.code(), message)
+            }
+            IgniteError::NotHandshaked => write!(f, "Handshake has not been performed"),
+            IgniteError::HandshakeFailed {
+                major_version,
+                minor_version,
+                patch_version,
+                message,
+            } => write!(
+                f,
+                "Handshake failed, server supports protocol version {}.{}.{}: {}",
+                major_version, minor_version, patch_version, message
+            ),
+            IgniteError::RequestIdMismatch { expected, actual } => write!(
+                f,
+                "Response request id {} does not match request id {}",
+                actual, expected
+            ),
+            IgniteError::ResponseTooLarge { size, max_size } => write!(
+                f,
+                "Response size {} exceeds max response size {}",
+                size, max_size
+            ),
+            IgniteError::TimedOut => write!(f, "Request timed out"),
+        }
+    }
+}
+
+impl std::error::Error for IgniteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IgniteError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for IgniteError {
+    fn from(e: io::Error) -> Self {
+        IgniteError::Io(e)
+    }
+}
+
+impl From<IgniteError> for io::Error {
+    fn from(e: IgniteError) -> Self {
+        match e {
+            IgniteError::Io(e) => e,
+            other => io::Error::other(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_status_from_code() {
+        assert_eq!(ServerStatus::from(1000), ServerStatus::CacheDoesNotExist);
+        assert_eq!(ServerStatus::from(2000), ServerStatus::AuthFailed);
+        assert_eq!(ServerStatus::from(4242), ServerStatus::Unknown(4242));
+        assert_eq!(ServerStatus::from(1010).code(), 1010);
+    }
+}
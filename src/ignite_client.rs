@@ -1,152 +1,852 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::io;
-use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use bytes::BytesMut;
+use socket2::{SockRef, TcpKeepalive};
 use tokio::net::TcpStream;
+use tokio::sync::Mutex;
 
+use crate::affinity::PartitionMap;
+use crate::error::{IgniteError, ServerStatus};
 use crate::protocol::{
-    HandshakeRequest, HandshakeResponse, QuerySqlFieldsRequest,
-    QuerySqlFieldsResponse, QuerySqlRequest, QuerySqlResponse, Request, Response, ResponseType,
+    CacheGetSizeRequest, CacheKeyRequest, CacheKeyValueRequest, CacheKeysRequest,
+    CachePartitionsRequest, CachePartitionsResponse, CacheReplaceIfEqualsRequest, CacheRequest,
+    HandshakeRequest, HandshakeResponse, PeekMode, QuerySqlFieldsRequest, QuerySqlFieldsResponse,
+    QuerySqlRequest, QuerySqlResponse, Request, Response, ResponseType, StatementType,
+    TopologyVersion,
 };
+use crate::request_id::RequestIdGenerator;
+use crate::value::{read_bytes, Value};
 
+const DEFAULT_MAX_RESPONSE_SIZE: usize = 256 * 1024 * 1024;
+const DEFAULT_TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+const DEFAULT_PARTITION_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+// clones share the same connection, requests from all clones are queued on it in order.
+// the connection is closed once the last clone is dropped
+#[derive(Clone)]
 pub struct IgniteClient {
+    connection: Arc<Mutex<Connection>>,
+}
+
+struct Connection {
     stream: Option<TcpStream>,
     host: String,
     port: u16,
-    request_id: AtomicI64,
+    request_id: RequestIdGenerator,
+    // response buffer reused across requests, grows to the largest response seen
+    read_buf: Vec<u8>,
+    max_response_size: usize,
+    keepalive: Option<Duration>,
+    // added to a query's timeout before the client gives up, so the server's timeout fires first
+    timeout_grace: Duration,
+    // result of the last handshake on the current connection
+    handshake: Option<HandshakeResponse>,
+    server_version: Option<(i16, i16, i16)>,
+    partition_map: PartitionMap,
+    partition_refresh_interval: Duration,
+    // set while a request is written and its response not yet read. still set when the next
+    // request starts means the previous one was cancelled and left its response on the stream
+    in_flight: bool,
 }
 
 impl IgniteClient {
     pub fn new(host: &str, port: u16) -> Self {
         IgniteClient {
+            connection: Arc::new(Mutex::new(Connection::new(host, port))),
+        }
+    }
+
+    pub async fn set_max_response_size(&self, max_response_size: usize) {
+        self.connection.lock().await.max_response_size = max_response_size;
+    }
+
+    // how long past a query's timeout_milliseconds the client waits for the server's own timeout
+    // error before it gives up and drops the connection
+    pub async fn set_timeout_grace(&self, timeout_grace: Duration) {
+        self.connection.lock().await.timeout_grace = timeout_grace;
+    }
+
+    // enables TCP keepalive with the given idle time and probe interval on subsequent connects
+    pub async fn set_keepalive(&self, keepalive: Option<Duration>) {
+        self.connection.lock().await.keepalive = keepalive;
+    }
+
+    pub async fn connect(&self) -> Result<(), IgniteError> {
+        self.connection.lock().await.connect().await
+    }
+
+    pub async fn connect_and_handshake(&self, request: HandshakeRequest) -> Result<(), IgniteError> {
+        self.connection
+            .lock()
+            .await
+            .connect_and_handshake(request)
+            .await
+    }
+
+    pub async fn handshake(
+        &self,
+        request: HandshakeRequest,
+    ) -> Result<HandshakeResponse, IgniteError> {
+        self.connection.lock().await.handshake(request).await
+    }
+
+    pub async fn query_sql(
+        &self,
+        request: QuerySqlRequest,
+    ) -> Result<QuerySqlResponse, IgniteError> {
+        self.connection.lock().await.query_sql(request).await
+    }
+
+    pub async fn query_sql_fields(
+        &self,
+        request: QuerySqlFieldsRequest,
+    ) -> Result<QuerySqlFieldsResponse, IgniteError> {
+        self.connection.lock().await.query_sql_fields(request).await
+    }
+
+    // maps the rows of the first page by column name
+    pub async fn query_sql_fields_mapped(
+        &self,
+        request: QuerySqlFieldsRequest,
+    ) -> Result<Vec<HashMap<String, Value>>, IgniteError> {
+        if !request.include_field_names {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "include_field_names is required to map rows by column name",
+            )
+            .into());
+        }
+        let response = self.query_sql_fields(request).await?;
+        let rows = response
+            .rows
+            .into_iter()
+            .map(|row| response.column_names.iter().cloned().zip(row).collect())
+            .collect();
+        Ok(rows)
+    }
+
+    // runs a DML statement and returns the number of affected rows
+    pub async fn execute(&self, request: QuerySqlFieldsRequest) -> Result<i64, IgniteError> {
+        check_dml(&request)?;
+        let response = self.query_sql_fields(request).await?;
+        affected_rows(&response)
+    }
+
+    // pipelines the DML statements, writing them all before reading the responses. each
+    // statement gets its own result in input order, a failing statement doesn't stop the rest.
+    // the outer error is a connection failure or timeout, after which the remaining results are
+    // unknown and the connection is dropped
+    pub async fn execute_batch(
+        &self,
+        requests: Vec<QuerySqlFieldsRequest>,
+    ) -> Result<Vec<Result<i64, IgniteError>>, IgniteError> {
+        self.connection.lock().await.execute_batch(requests).await
+    }
+
+    pub async fn contains_key(&self, cache_id: i32, key: Value) -> Result<bool, IgniteError> {
+        let request = CacheKeyRequest::new(cache_id, key);
+        self.connection
+            .lock()
+            .await
+            .request_bool(|request_id| Request::new_cache_contains_key(request_id, request))
+            .await
+    }
+
+    pub async fn put_if_absent(
+        &self,
+        cache_id: i32,
+        key: Value,
+        value: Value,
+    ) -> Result<bool, IgniteError> {
+        let request = CacheKeyValueRequest::new(cache_id, key, value);
+        self.connection
+            .lock()
+            .await
+            .request_bool(|request_id| Request::new_cache_put_if_absent(request_id, request))
+            .await
+    }
+
+    pub async fn replace(
+        &self,
+        cache_id: i32,
+        key: Value,
+        value: Value,
+    ) -> Result<bool, IgniteError> {
+        let request = CacheKeyValueRequest::new(cache_id, key, value);
+        self.connection
+            .lock()
+            .await
+            .request_bool(|request_id| Request::new_cache_replace(request_id, request))
+            .await
+    }
+
+    pub async fn replace_if_equals(
+        &self,
+        cache_id: i32,
+        key: Value,
+        old_value: Value,
+        new_value: Value,
+    ) -> Result<bool, IgniteError> {
+        let request = CacheReplaceIfEqualsRequest::new(cache_id, key, old_value, new_value);
+        self.connection
+            .lock()
+            .await
+            .request_bool(|request_id| Request::new_cache_replace_if_equals(request_id, request))
+            .await
+    }
+
+    pub async fn remove_key(&self, cache_id: i32, key: Value) -> Result<bool, IgniteError> {
+        let request = CacheKeyRequest::new(cache_id, key);
+        self.connection
+            .lock()
+            .await
+            .request_bool(|request_id| Request::new_cache_remove_key(request_id, request))
+            .await
+    }
+
+    // clears the cache without notifying listeners or writing through to the cache store
+    pub async fn clear(&self, cache_id: i32) -> Result<(), IgniteError> {
+        let request = CacheRequest::new(cache_id);
+        self.connection
+            .lock()
+            .await
+            .request_empty(|request_id| Request::new_cache_clear(request_id, request))
+            .await
+    }
+
+    pub async fn clear_key(&self, cache_id: i32, key: Value) -> Result<(), IgniteError> {
+        let request = CacheKeyRequest::new(cache_id, key);
+        self.connection
+            .lock()
+            .await
+            .request_empty(|request_id| Request::new_cache_clear_key(request_id, request))
+            .await
+    }
+
+    pub async fn clear_keys(&self, cache_id: i32, keys: Vec<Value>) -> Result<(), IgniteError> {
+        let request = CacheKeysRequest::new(cache_id, keys);
+        self.connection
+            .lock()
+            .await
+            .request_empty(|request_id| Request::new_cache_clear_keys(request_id, request))
+            .await
+    }
+
+    pub async fn get_and_remove(
+        &self,
+        cache_id: i32,
+        key: Value,
+    ) -> Result<Option<Value>, IgniteError> {
+        self.connection
+            .lock()
+            .await
+            .get_and_remove(cache_id, key)
+            .await
+    }
+
+    pub async fn cache_size(
+        &self,
+        cache_id: i32,
+        peek_modes: Vec<PeekMode>,
+    ) -> Result<i64, IgniteError> {
+        self.connection
+            .lock()
+            .await
+            .cache_size(cache_id, peek_modes)
+            .await
+    }
+
+    // fetches the partition distribution of the caches and caches it for primary_node
+    pub async fn cache_partitions(
+        &self,
+        cache_ids: Vec<i32>,
+    ) -> Result<CachePartitionsResponse, IgniteError> {
+        self.connection.lock().await.cache_partitions(cache_ids).await
+    }
+
+    // the id of the node owning the primary partition of the key, fetching the partition
+    // distribution of the cache on first use. None when the partitions of the cache or key
+    // can't be computed on the client. crate-private until requests are routed by it, the
+    // client only ever talks to the one node it connected to
+    #[allow(dead_code)]
+    pub(crate) async fn primary_node(
+        &self,
+        cache_id: i32,
+        key: &Value,
+    ) -> Result<Option<u128>, IgniteError> {
+        self.connection.lock().await.primary_node(cache_id, key).await
+    }
+
+    // how long primary_node trusts the partition distribution before fetching it again to check
+    // the topology version. zero checks on every lookup
+    #[allow(dead_code)]
+    pub(crate) async fn set_partition_refresh_interval(&self, partition_refresh_interval: Duration) {
+        self.connection.lock().await.partition_refresh_interval = partition_refresh_interval;
+    }
+
+    // the affinity topology version the cached partition distribution was fetched at
+    pub async fn topology_version(&self) -> Option<TopologyVersion> {
+        self.connection.lock().await.partition_map.topology_version()
+    }
+
+    // sends an op the client doesn't model yet. the returned payload excludes the length prefix
+    // and starts with the response header, a non-zero status is returned as an error
+    pub async fn raw_request(&self, op_code: i16, body: BytesMut) -> Result<Vec<u8>, IgniteError> {
+        self.connection.lock().await.raw_request(op_code, body).await
+    }
+
+    // the protocol version negotiated by the last successful handshake
+    pub async fn server_version(&self) -> Option<(i16, i16, i16)> {
+        self.connection.lock().await.server_version
+    }
+
+    // returns whether an open connection was closed, the client can connect again afterwards
+    pub async fn close(&self) -> Result<bool, IgniteError> {
+        self.connection.lock().await.close().await
+    }
+}
+
+impl Connection {
+    fn new(host: &str, port: u16) -> Self {
+        Connection {
             stream: None,
             host: host.to_string(),
             port,
-            request_id: AtomicI64::new(0),
+            request_id: RequestIdGenerator::new(),
+            read_buf: Vec::new(),
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            keepalive: None,
+            timeout_grace: DEFAULT_TIMEOUT_GRACE,
+            handshake: None,
+            server_version: None,
+            partition_map: PartitionMap::default(),
+            partition_refresh_interval: DEFAULT_PARTITION_REFRESH_INTERVAL,
+            in_flight: false,
         }
     }
 
-    pub async fn connect(&mut self) -> Result<(), io::Error> {
+    async fn connect(&mut self) -> Result<(), IgniteError> {
         let addr = format!("{}:{}", self.host, self.port);
         let stream = TcpStream::connect(addr).await?;
+        if let Some(interval) = self.keepalive {
+            let keepalive = TcpKeepalive::new()
+                .with_time(interval)
+                .with_interval(interval);
+            SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
+        }
+        self.reset();
         self.stream = Some(stream);
         Ok(())
     }
 
-    pub async fn handshake(
+    async fn connect_and_handshake(
+        &mut self,
+        request: HandshakeRequest,
+    ) -> Result<(), IgniteError> {
+        self.connect().await?;
+        match self.handshake(request).await? {
+            HandshakeResponse::Success => Ok(()),
+            HandshakeResponse::Failure {
+                major_version,
+                minor_version,
+                patch_version,
+                error_message,
+            } => Err(IgniteError::HandshakeFailed {
+                major_version,
+                minor_version,
+                patch_version,
+                message: error_message,
+            }),
+        }
+    }
+
+    async fn handshake(
         &mut self,
         request: HandshakeRequest,
-    ) -> Result<HandshakeResponse, io::Error> {
-        if let Some(stream) = &mut self.stream {
-            let encoded_request = request.encode();
-            stream.write_all(&encoded_request).await?;
+    ) -> Result<HandshakeResponse, IgniteError> {
+        self.reset_if_in_flight();
+        self.write_frame(&request.encode()).await?;
+        let response = HandshakeResponse::decode(self.read_frame().await?)?;
+        self.server_version = match response {
+            HandshakeResponse::Success => Some((
+                request.major_version,
+                request.minor_version,
+                request.patch_version,
+            )),
+            HandshakeResponse::Failure { .. } => None,
+        };
+        self.handshake = Some(response.clone());
+        Ok(response)
+    }
 
-            let mut length_buf = [0u8; 4];
-            stream.read_exact(&mut length_buf).await?;
-            let msg_length = u32::from_le_bytes(length_buf) as usize;
+    async fn query_sql(
+        &mut self,
+        request: QuerySqlRequest,
+    ) -> Result<QuerySqlResponse, IgniteError> {
+        let timeout_milliseconds = request.timeout_milliseconds;
+        let timeout_grace = self.timeout_grace;
+        let result = with_timeout(
+            timeout_milliseconds,
+            timeout_grace,
+            self.query_sql_once(request),
+        )
+        .await;
+        self.reset_on_timeout(result)
+    }
 
-            let mut msg_buf = vec![0u8; msg_length];
-            stream.read_exact(&mut msg_buf).await?;
+    async fn query_sql_once(
+        &mut self,
+        request: QuerySqlRequest,
+    ) -> Result<QuerySqlResponse, IgniteError> {
+        let request_id = self.request_id.next();
+        let request = Request::new_query_sql(request_id, request);
+        self.round_trip(request).await?;
 
-            let response = HandshakeResponse::decode(&msg_buf)?;
-            Ok(response)
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotConnected, "Not connected"))
+        let response = Response::decode_query_sql(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::QuerySql(query_sql) => Ok(query_sql),
+            _ => Err(io::Error::other("Unexpected response type").into()),
         }
     }
 
-    pub async fn query_sql(
+    async fn query_sql_fields(
         &mut self,
-        request: QuerySqlRequest,
-    ) -> Result<QuerySqlResponse, io::Error> {
-        if let Some(stream) = &mut self.stream {
-            let request_id = self.request_id.fetch_add(1, Ordering::SeqCst);
-            let encoded_request = Request::new_query_sql(request_id, request).encode();
-            stream.write_all(&encoded_request).await?;
+        request: QuerySqlFieldsRequest,
+    ) -> Result<QuerySqlFieldsResponse, IgniteError> {
+        let timeout_milliseconds = request.timeout_milliseconds;
+        let timeout_grace = self.timeout_grace;
+        let result = with_timeout(
+            timeout_milliseconds,
+            timeout_grace,
+            self.query_sql_fields_once(request),
+        )
+        .await;
+        self.reset_on_timeout(result)
+    }
 
-            let mut length_buf = [0u8; 4];
-            stream.read_exact(&mut length_buf).await?;
-            let msg_length = u32::from_le_bytes(length_buf) as usize;
+    async fn query_sql_fields_once(
+        &mut self,
+        request: QuerySqlFieldsRequest,
+    ) -> Result<QuerySqlFieldsResponse, IgniteError> {
+        let request_id = self.request_id.next();
+        let include_field_names = request.include_field_names;
+        let request = Request::new_query_sql_fields(request_id, request);
+        self.round_trip(request).await?;
 
-            let mut msg_buf = vec![0u8; msg_length];
-            stream.read_exact(&mut msg_buf).await?;
+        let response = Response::decode_query_sql_fields(&self.read_buf, include_field_names)?;
+        match check_response(request_id, response)? {
+            ResponseType::QuerySqlFields(query_sql_fields) => Ok(query_sql_fields),
+            _ => Err(io::Error::other("Unexpected response type").into()),
+        }
+    }
 
-            let response = Response::decode_query_sql(&msg_buf)?;
-            if response.status_code != 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Error: {}", response.error_message),
-                ));
+    // the statements may run one after another on the server, so the batch gets the sum of
+    // their timeouts. any statement without a timeout leaves the batch without one
+    async fn execute_batch(
+        &mut self,
+        requests: Vec<QuerySqlFieldsRequest>,
+    ) -> Result<Vec<Result<i64, IgniteError>>, IgniteError> {
+        let timeout_milliseconds = if requests.iter().any(|r| r.timeout_milliseconds <= 0) {
+            0
+        } else {
+            requests
+                .iter()
+                .fold(0i64, |total, r| total.saturating_add(r.timeout_milliseconds))
+        };
+        let timeout_grace = self.timeout_grace;
+        let result = with_timeout(
+            timeout_milliseconds,
+            timeout_grace,
+            self.execute_batch_once(requests),
+        )
+        .await;
+        // a transport error, an oversized or unknown response or a timeout leaves the remaining
+        // responses in flight
+        if result.is_err() {
+            self.reset_if_in_flight();
+        }
+        result
+    }
+
+    async fn execute_batch_once(
+        &mut self,
+        requests: Vec<QuerySqlFieldsRequest>,
+    ) -> Result<Vec<Result<i64, IgniteError>>, IgniteError> {
+        self.reset_if_in_flight();
+        self.check_handshake()?;
+        let mut results: Vec<Option<Result<i64, IgniteError>>> =
+            Vec::with_capacity(requests.len());
+        // request id -> (index in the batch, whether the response includes field names)
+        let mut pending = HashMap::new();
+        let mut frames = BytesMut::new();
+        for (index, request) in requests.into_iter().enumerate() {
+            if let Err(e) = check_dml(&request) {
+                results.push(Some(Err(e)));
+                continue;
             }
-            match response.body {
-                ResponseType::QuerySql(query_sql) => Ok(query_sql),
-                _ => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Unexpected response type",
-                )),
+            let request_id = self.request_id.next();
+            let include_field_names = request.include_field_names;
+            match Request::new_query_sql_fields(request_id, request).encode() {
+                Ok(frame) => frames.extend_from_slice(&frame),
+                Err(e) => {
+                    results.push(Some(Err(e.into())));
+                    continue;
+                }
             }
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotConnected, "Not connected"))
+            pending.insert(request_id, (index, include_field_names));
+            results.push(None);
+        }
+
+        let max_response_size = self.max_response_size;
+        self.in_flight = true;
+        let read_buf = &mut self.read_buf;
+        let stream = self.stream.as_mut().ok_or_else(not_connected)?;
+        let (mut reader, mut writer) = stream.split();
+        // responses are read while the requests are still being written, so neither side of the
+        // connection stalls on a full socket buffer
+        let write = async {
+            writer.write_all(&frames).await?;
+            Ok(())
+        };
+        let read = async {
+            while !pending.is_empty() {
+                read_frame_from(&mut reader, read_buf, max_response_size).await?;
+                let request_id =
+                    i64::from_le_bytes(read_bytes(read_buf, &mut 0, 8)?.try_into().unwrap());
+                let (index, include_field_names) =
+                    pending.remove(&request_id).ok_or_else(|| IgniteError::RequestIdMismatch {
+                        expected: *pending.keys().min().unwrap(),
+                        actual: request_id,
+                    })?;
+                let result = Response::decode_query_sql_fields(read_buf, include_field_names)
+                    .map_err(IgniteError::from)
+                    .and_then(|response| check_response(request_id, response))
+                    .and_then(|body| match body {
+                        ResponseType::QuerySqlFields(query_sql_fields) => {
+                            affected_rows(&query_sql_fields)
+                        }
+                        _ => Err(io::Error::other("Unexpected response type").into()),
+                    });
+                results[index] = Some(result);
+            }
+            Ok::<_, IgniteError>(())
+        };
+        tokio::try_join!(write, read)?;
+        self.in_flight = false;
+
+        // every statement was either rejected up front or has received its response
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    // a timed out request leaves a partial response on the stream, so the connection is dropped.
+    // the server closes any cursor the request opened along with the connection
+    fn reset_on_timeout<T>(&mut self, result: Result<T, IgniteError>) -> Result<T, IgniteError> {
+        if matches!(result, Err(IgniteError::TimedOut)) {
+            self.reset();
         }
+        result
     }
 
-    pub async fn query_sql_fields(
+    // drops the connection along with everything learned on it, the client has to connect again
+    fn reset(&mut self) {
+        self.stream = None;
+        self.handshake = None;
+        self.server_version = None;
+        self.partition_map.clear();
+        self.in_flight = false;
+    }
+
+    // a cancelled request leaves the stream out of sync, so the connection is dropped
+    fn reset_if_in_flight(&mut self) {
+        if self.in_flight {
+            self.reset();
+        }
+    }
+
+    async fn raw_request(&mut self, op_code: i16, body: BytesMut) -> Result<Vec<u8>, IgniteError> {
+        let request_id = self.request_id.next();
+        self.round_trip(Request::new_raw(request_id, op_code, body)).await?;
+
+        let response = Response::decode_raw(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::Raw(payload) => Ok(payload),
+            _ => Err(io::Error::other("Unexpected response type").into()),
+        }
+    }
+
+    // sends a request answered with nothing but the status
+    async fn request_empty(
         &mut self,
-        request: QuerySqlFieldsRequest,
-    ) -> Result<QuerySqlFieldsResponse, io::Error> {
-        if let Some(stream) = &mut self.stream {
-            let request_id = self.request_id.fetch_add(1, Ordering::SeqCst);
-            let encoded_request = Request::new_query_sql_fields(request_id, request).encode();
-            stream.write_all(&encoded_request).await?;
+        request: impl FnOnce(i64) -> Request,
+    ) -> Result<(), IgniteError> {
+        let request_id = self.request_id.next();
+        self.round_trip(request(request_id)).await?;
 
-            let mut length_buf = [0u8; 4];
-            stream.read_exact(&mut length_buf).await?;
-            let msg_length = u32::from_le_bytes(length_buf) as usize;
+        let response = Response::decode_empty(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::Empty => Ok(()),
+            _ => Err(io::Error::other("Unexpected response type").into()),
+        }
+    }
 
-            let mut msg_buf = vec![0u8; msg_length];
-            stream.read_exact(&mut msg_buf).await?;
+    // sends a request answered with a single boolean
+    async fn request_bool(
+        &mut self,
+        request: impl FnOnce(i64) -> Request,
+    ) -> Result<bool, IgniteError> {
+        let request_id = self.request_id.next();
+        self.round_trip(request(request_id)).await?;
 
-            let response = Response::decode_query_sql_fields(&msg_buf, true)?;
-            if response.status_code != 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Error: {}", response.error_message),
-                ));
-            }
-            match response.body {
-                ResponseType::QuerySqlFields(query_sql_fields) => Ok(query_sql_fields),
-                _ => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Unexpected response type",
-                )),
+        let response = Response::decode_bool(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::Bool(value) => Ok(value),
+            _ => Err(io::Error::other("Unexpected response type").into()),
+        }
+    }
+
+    async fn get_and_remove(
+        &mut self,
+        cache_id: i32,
+        key: Value,
+    ) -> Result<Option<Value>, IgniteError> {
+        let request_id = self.request_id.next();
+        let request =
+            Request::new_cache_get_and_remove(request_id, CacheKeyRequest::new(cache_id, key));
+        self.round_trip(request).await?;
+
+        let response = Response::decode_value(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::Value(Value::Null) => Ok(None),
+            ResponseType::Value(value) => Ok(Some(value)),
+            _ => Err(io::Error::other("Unexpected response type").into()),
+        }
+    }
+
+    async fn cache_size(
+        &mut self,
+        cache_id: i32,
+        peek_modes: Vec<PeekMode>,
+    ) -> Result<i64, IgniteError> {
+        let request_id = self.request_id.next();
+        let request = Request::new_cache_get_size(
+            request_id,
+            CacheGetSizeRequest::new(cache_id, peek_modes),
+        );
+        self.round_trip(request).await?;
+
+        let response = Response::decode_i64(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::I64(size) => Ok(size),
+            _ => Err(io::Error::other("Unexpected response type").into()),
+        }
+    }
+
+    async fn cache_partitions(
+        &mut self,
+        cache_ids: Vec<i32>,
+    ) -> Result<CachePartitionsResponse, IgniteError> {
+        let request_id = self.request_id.next();
+        let request =
+            Request::new_cache_partitions(request_id, CachePartitionsRequest::new(cache_ids));
+        self.round_trip(request).await?;
+
+        let response = Response::decode_cache_partitions(&self.read_buf)?;
+        match check_response(request_id, response)? {
+            ResponseType::CachePartitions(cache_partitions) => {
+                self.partition_map.update(&cache_partitions);
+                Ok(cache_partitions)
             }
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotConnected, "Not connected"))
+            _ => Err(io::Error::other("Unexpected response type").into()),
         }
     }
 
-    pub async fn close(&mut self) -> Result<(), io::Error> {
-        if let Some(mut stream) = self.stream.take() {
-            stream.shutdown().await?;
+    async fn primary_node(
+        &mut self,
+        cache_id: i32,
+        key: &Value,
+    ) -> Result<Option<u128>, IgniteError> {
+        // protocol 1.0.0 responses don't flag topology changes, so a bump is only seen by
+        // fetching again. a newer version drops the partitions of every other cache
+        if !self.partition_map.contains(cache_id)
+            || self.partition_map.is_stale(self.partition_refresh_interval)
+        {
+            self.cache_partitions(vec![cache_id]).await?;
         }
+        Ok(self.partition_map.primary_node(cache_id, key))
+    }
+
+    // sends the request and reads the response payload into read_buf
+    async fn round_trip(&mut self, request: Request) -> Result<(), IgniteError> {
+        self.reset_if_in_flight();
+        self.check_handshake()?;
+        self.write_frame(&request.encode()?).await?;
+        let payload = self.read_frame().await?;
+
+        // any other response means the stream is out of sync, later responses can't be trusted
+        let actual = i64::from_le_bytes(read_bytes(payload, &mut 0, 8)?.try_into().unwrap());
+        if actual != request.request_id {
+            self.reset();
+            return Err(IgniteError::RequestIdMismatch {
+                expected: request.request_id,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    async fn write_frame(&mut self, frame: &[u8]) -> Result<(), IgniteError> {
+        let stream = self.stream.as_mut().ok_or_else(not_connected)?;
+        self.in_flight = true;
+        stream.write_all(frame).await?;
         Ok(())
     }
+
+    // reads a length prefixed frame into read_buf and returns its payload
+    async fn read_frame(&mut self) -> Result<&[u8], IgniteError> {
+        let stream = self.stream.as_mut().ok_or_else(not_connected)?;
+        match read_frame_from(stream, &mut self.read_buf, self.max_response_size).await {
+            Ok(()) => self.in_flight = false,
+            // the length may be garbage from a desynced stream, so the payload isn't waited for
+            Err(e @ IgniteError::ResponseTooLarge { .. }) => {
+                self.reset();
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(&self.read_buf)
+    }
+
+    fn check_handshake(&self) -> Result<(), IgniteError> {
+        match &self.handshake {
+            Some(HandshakeResponse::Success) => Ok(()),
+            Some(HandshakeResponse::Failure {
+                major_version,
+                minor_version,
+                patch_version,
+                error_message,
+            }) => Err(IgniteError::HandshakeFailed {
+                major_version: *major_version,
+                minor_version: *minor_version,
+                patch_version: *patch_version,
+                message: error_message.clone(),
+            }),
+            None => Err(IgniteError::NotHandshaked),
+        }
+    }
+
+    async fn close(&mut self) -> Result<bool, IgniteError> {
+        let stream = self.stream.take();
+        self.reset();
+        self.read_buf = Vec::new();
+        match stream {
+            Some(mut stream) => {
+                stream.shutdown().await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+// a timeout of zero or less waits indefinitely, matching the server side timeout
+async fn with_timeout<T>(
+    timeout_milliseconds: i64,
+    grace: Duration,
+    future: impl Future<Output = Result<T, IgniteError>>,
+) -> Result<T, IgniteError> {
+    if timeout_milliseconds <= 0 {
+        return future.await;
+    }
+    let timeout = Duration::from_millis(timeout_milliseconds as u64) + grace;
+    tokio::time::timeout(timeout, future)
+        .await
+        .unwrap_or(Err(IgniteError::TimedOut))
+}
+
+async fn read_frame_from(
+    reader: &mut (impl AsyncRead + Unpin),
+    read_buf: &mut Vec<u8>,
+    max_response_size: usize,
+) -> Result<(), IgniteError> {
+    let mut length_buf = [0u8; 4];
+    reader.read_exact(&mut length_buf).await?;
+    let msg_length = u32::from_le_bytes(length_buf) as usize;
+    if msg_length > max_response_size {
+        return Err(IgniteError::ResponseTooLarge {
+            size: msg_length,
+            max_size: max_response_size,
+        });
+    }
+
+    read_buf.clear();
+    read_buf.resize(msg_length, 0);
+    reader.read_exact(read_buf).await?;
+    Ok(())
+}
+
+fn check_dml(request: &QuerySqlFieldsRequest) -> Result<(), IgniteError> {
+    if matches!(request.statement_type, StatementType::SELECT) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "execute does not accept SELECT statements",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn affected_rows(response: &QuerySqlFieldsResponse) -> Result<i64, IgniteError> {
+    response.affected_rows().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Statement did not return an affected row count",
+        )
+        .into()
+    })
+}
+
+fn not_connected() -> IgniteError {
+    io::Error::new(io::ErrorKind::NotConnected, "Not connected").into()
+}
+
+fn check_response(request_id: i64, response: Response) -> Result<ResponseType, IgniteError> {
+    if response.request_id != request_id {
+        return Err(IgniteError::RequestIdMismatch {
+            expected: request_id,
+            actual: response.request_id,
+        });
+    }
+    if response.status_code != 0 {
+        return Err(IgniteError::Server {
+            status: ServerStatus::from(response.status_code),
+            message: response.error_message,
+        });
+    }
+    Ok(response.body)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::{QuerySqlFieldsRequest, StatementType};
+    use bytes::BufMut;
+    use crate::protocol::cache_id;
+    use crate::value::Encode;
 
     #[tokio::test]
     async fn test_handshake_success() -> io::Result<()> {
-        let mut client = IgniteClient::new("127.0.0.1", 10800);
+        let client = IgniteClient::new("127.0.0.1", 10800);
         client.connect().await?;
 
         let request = HandshakeRequest::new(1, 0, 0, "".to_string(), "".to_string());
         let response = client.handshake(request).await?;
 
         assert!(matches!(response, HandshakeResponse::Success));
+        assert_eq!(client.server_version().await, Some((1, 0, 0)));
 
         client.close().await?;
         Ok(())
@@ -154,13 +854,94 @@ mod tests {
 
     #[tokio::test]
     async fn test_handshake_fail() -> io::Result<()> {
-        let mut client = IgniteClient::new("127.0.0.1", 10800);
+        let client = IgniteClient::new("127.0.0.1", 10800);
         client.connect().await?;
 
         let request = HandshakeRequest::new(2, 15, 0, "".to_string(), "".to_string());
         let response = client.handshake(request).await?;
 
         assert!(matches!(response, HandshakeResponse::Failure { .. }));
+        assert_eq!(client.server_version().await, None);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_keepalive() -> io::Result<()> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+        client.set_keepalive(Some(Duration::from_secs(30))).await;
+        client.connect().await?;
+
+        let connection = client.connection.lock().await;
+        assert!(SockRef::from(connection.stream.as_ref().unwrap()).keepalive()?);
+        drop(connection);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_handshake_fail() -> io::Result<()> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+
+        let request = HandshakeRequest::new(2, 15, 0, "".to_string(), "".to_string());
+        let result = client.connect_and_handshake(request).await;
+
+        assert!(matches!(result, Err(IgniteError::HandshakeFailed { .. })));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sql_fields_not_handshaked() -> io::Result<()> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+        client.connect().await?;
+
+        let result = client.query_sql_fields(select_schemas_request()).await;
+
+        assert!(matches!(result, Err(IgniteError::NotHandshaked)));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sql_fields_response_too_large() -> io::Result<()> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+        client
+            .connect_and_handshake(HandshakeRequest::new(
+                1,
+                0,
+                0,
+                "".to_string(),
+                "".to_string(),
+            ))
+            .await?;
+        client.set_max_response_size(8).await;
+
+        let result = client.query_sql_fields(select_schemas_request()).await;
+
+        assert!(matches!(result, Err(IgniteError::ResponseTooLarge { .. })));
+
+        // the connection was dropped, the client has to connect again
+        let result = client.query_sql_fields(select_schemas_request()).await;
+        assert!(matches!(result, Err(IgniteError::NotHandshaked)));
+        assert!(!client.close().await?);
+
+        client.set_max_response_size(DEFAULT_MAX_RESPONSE_SIZE).await;
+        client
+            .connect_and_handshake(HandshakeRequest::new(
+                1,
+                0,
+                0,
+                "".to_string(),
+                "".to_string(),
+            ))
+            .await?;
+        let response = client.query_sql_fields(select_schemas_request()).await?;
+        assert!(!response.rows.is_empty());
 
         client.close().await?;
         Ok(())
@@ -168,7 +949,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_query_sql_fields_success() -> io::Result<()> {
-        let mut client = IgniteClient::new("127.0.0.1", 10800);
+        let client = IgniteClient::new("127.0.0.1", 10800);
         client.connect().await?;
         client
             .handshake(HandshakeRequest::new(
@@ -186,7 +967,6 @@ mod tests {
             1024,
             65535,
             "SELECT * FROM SYS.SCHEMAS".to_string(),
-            0,
             Vec::new(),
             StatementType::SELECT,
             false,
@@ -200,9 +980,621 @@ mod tests {
         );
         let response = client.query_sql_fields(request).await?;
 
-        assert!(response.column_names.len() > 0);
+        assert!(!response.column_names.is_empty());
 
         client.close().await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_query_sql_fields_mapped() -> io::Result<()> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+        client
+            .connect_and_handshake(HandshakeRequest::new(
+                1,
+                0,
+                0,
+                "".to_string(),
+                "".to_string(),
+            ))
+            .await?;
+
+        let rows = client
+            .query_sql_fields_mapped(select_schemas_request())
+            .await?;
+
+        assert!(!rows.is_empty());
+        assert!(rows
+            .iter()
+            .any(|row| row.get("SCHEMA_NAME") == Some(&Value::String("PUBLIC".to_string()))));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_contains_key() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "CONTAINS_KEY_TEST").await?;
+
+        assert!(!client.contains_key(cache_id, Value::I32(1)).await?);
+        client
+            .query_sql_fields(sql_fields_request(
+                "INSERT INTO CONTAINS_KEY_TEST (ID, V) VALUES (1, 'a')",
+                StatementType::ANY,
+            ))
+            .await?;
+        assert!(client.contains_key(cache_id, Value::I32(1)).await?);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_key() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "REMOVE_KEY_TEST").await?;
+
+        client
+            .query_sql_fields(sql_fields_request(
+                "INSERT INTO REMOVE_KEY_TEST (ID, V) VALUES (1, 'a')",
+                StatementType::ANY,
+            ))
+            .await?;
+        assert!(client.remove_key(cache_id, Value::I32(1)).await?);
+        assert!(!client.contains_key(cache_id, Value::I32(1)).await?);
+        assert!(!client.remove_key(cache_id, Value::I32(1)).await?);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_and_remove() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "GET_AND_REMOVE_TEST").await?;
+
+        client
+            .query_sql_fields(sql_fields_request(
+                "INSERT INTO GET_AND_REMOVE_TEST (ID, V) VALUES (1, 'a')",
+                StatementType::ANY,
+            ))
+            .await?;
+        assert_eq!(
+            client.get_and_remove(cache_id, Value::I32(1)).await?,
+            Some(Value::String("a".to_string()))
+        );
+        assert_eq!(client.get_and_remove(cache_id, Value::I32(1)).await?, None);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_size() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "CACHE_SIZE_TEST").await?;
+
+        assert_eq!(client.cache_size(cache_id, vec![]).await?, 0);
+        client
+            .query_sql_fields(sql_fields_request(
+                "INSERT INTO CACHE_SIZE_TEST (ID, V) VALUES (1, 'a')",
+                StatementType::ANY,
+            ))
+            .await?;
+        client
+            .query_sql_fields(sql_fields_request(
+                "INSERT INTO CACHE_SIZE_TEST (ID, V) VALUES (2, 'b')",
+                StatementType::ANY,
+            ))
+            .await?;
+        assert_eq!(client.cache_size(cache_id, vec![]).await?, 2);
+        assert_eq!(client.cache_size(cache_id, vec![PeekMode::PRIMARY]).await?, 2);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clear() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "CLEAR_TEST").await?;
+        for id in 1..=4 {
+            client
+                .put_if_absent(cache_id, Value::I32(id), Value::String(id.to_string()))
+                .await?;
+        }
+
+        client.clear_key(cache_id, Value::I32(1)).await?;
+        assert!(!client.contains_key(cache_id, Value::I32(1)).await?);
+        client
+            .clear_keys(cache_id, vec![Value::I32(2), Value::I32(3)])
+            .await?;
+        assert_eq!(client.cache_size(cache_id, vec![]).await?, 1);
+        client.clear(cache_id).await?;
+        assert_eq!(client.cache_size(cache_id, vec![]).await?, 0);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cloned_clients_share_connection() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cloned = client.clone();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.query_sql_fields(select_schemas_request()).await })
+            })
+            .collect();
+        for handle in handles {
+            assert!(!handle.await.unwrap()?.column_names.is_empty());
+        }
+        drop(client);
+
+        let response = cloned.query_sql_fields(select_schemas_request()).await?;
+        assert!(!response.column_names.is_empty());
+
+        cloned.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        create_kv_table(&client, "EXECUTE_TEST").await?;
+
+        let affected_rows = client
+            .execute(sql_fields_request(
+                "INSERT INTO EXECUTE_TEST (ID, V) VALUES (1, 'a')",
+                StatementType::UPDATE,
+            ))
+            .await?;
+        assert_eq!(affected_rows, 1);
+
+        let result = client.execute(select_schemas_request()).await;
+        assert!(matches!(result, Err(IgniteError::Io(_))));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_args() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "EXECUTE_ARGS_TEST").await?;
+
+        let request = sql_fields_request(
+            "INSERT INTO EXECUTE_ARGS_TEST (ID, V) VALUES (?, ?)",
+            StatementType::UPDATE,
+        )
+        .arg(7)
+        .arg("seven");
+        assert_eq!(client.execute(request).await?, 1);
+        assert!(client.contains_key(cache_id, Value::I32(7)).await?);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute_batch() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "EXECUTE_BATCH_TEST").await?;
+
+        let insert = "INSERT INTO EXECUTE_BATCH_TEST (ID, V) VALUES (?, ?)";
+        let results = client
+            .execute_batch(vec![
+                sql_fields_request(insert, StatementType::UPDATE).arg(1).arg("a"),
+                sql_fields_request("FAIL", StatementType::UPDATE),
+                select_schemas_request(),
+                sql_fields_request(insert, StatementType::UPDATE).arg(2).arg("b"),
+            ])
+            .await?;
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[0], Ok(1)));
+        assert!(matches!(results[1], Err(IgniteError::Server { .. })));
+        assert!(matches!(results[2], Err(IgniteError::Io(_))));
+        assert!(matches!(results[3], Ok(1)));
+        assert_eq!(client.cache_size(cache_id, vec![]).await?, 2);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_if_absent() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "PUT_IF_ABSENT_TEST").await?;
+
+        let a = Value::String("a".to_string());
+        let b = Value::String("b".to_string());
+        assert!(client.put_if_absent(cache_id, Value::I32(1), a.clone()).await?);
+        assert!(!client.put_if_absent(cache_id, Value::I32(1), b).await?);
+        assert_eq!(client.get_and_remove(cache_id, Value::I32(1)).await?, Some(a));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replace() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "REPLACE_TEST").await?;
+
+        let a = Value::String("a".to_string());
+        let b = Value::String("b".to_string());
+        let c = Value::String("c".to_string());
+        assert!(!client.replace(cache_id, Value::I32(1), a.clone()).await?);
+        assert!(!client.contains_key(cache_id, Value::I32(1)).await?);
+
+        client.put_if_absent(cache_id, Value::I32(1), a.clone()).await?;
+        assert!(client.replace(cache_id, Value::I32(1), b.clone()).await?);
+        assert!(!client
+            .replace_if_equals(cache_id, Value::I32(1), a, c.clone())
+            .await?);
+        assert!(client
+            .replace_if_equals(cache_id, Value::I32(1), b, c.clone())
+            .await?);
+        assert_eq!(client.get_and_remove(cache_id, Value::I32(1)).await?, Some(c));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close_never_connected() -> io::Result<()> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+
+        assert!(!client.close().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_close() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        assert!(client.close().await?);
+        assert!(!client.close().await?);
+
+        let result = client.query_sql_fields(select_schemas_request()).await;
+        assert!(matches!(result, Err(IgniteError::NotHandshaked)));
+        assert_eq!(client.server_version().await, None);
+
+        client
+            .connect_and_handshake(HandshakeRequest::new(
+                1,
+                0,
+                0,
+                "".to_string(),
+                "".to_string(),
+            ))
+            .await?;
+        let response = client.query_sql_fields(select_schemas_request()).await?;
+        assert!(!response.column_names.is_empty());
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sql_fields_client_timeout() -> io::Result<()> {
+        // a server that completes the handshake and then doesn't answer in time
+        let port = spawn_fake_server(|request_id| {
+            (Duration::from_secs(10), bool_response(request_id, true))
+        })
+        .await?;
+        let client = fake_server_client(port).await?;
+        client.set_timeout_grace(Duration::from_millis(50)).await;
+        let mut request = select_schemas_request();
+        request.timeout_milliseconds = 100;
+
+        let result = client.query_sql_fields(request).await;
+        assert!(matches!(result, Err(IgniteError::TimedOut)));
+        assert!(!client.close().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sql_fields_server_timeout_within_grace() -> io::Result<()> {
+        // the server's own timeout error arrives just after timeout_milliseconds
+        let port = spawn_fake_server(|request_id| {
+            let mut payload = BytesMut::new();
+            payload.put_i64_le(request_id);
+            payload.put_i32_le(1);
+            Value::String("The query was cancelled while executing.".to_string())
+                .encode(&mut payload)
+                .unwrap();
+            (Duration::from_millis(150), payload.to_vec())
+        })
+        .await?;
+        let client = fake_server_client(port).await?;
+        let mut request = select_schemas_request();
+        request.timeout_milliseconds = 100;
+
+        let result = client.query_sql_fields(request).await;
+        assert!(matches!(
+            result,
+            Err(IgniteError::Server {
+                status: ServerStatus::Failed,
+                ..
+            })
+        ));
+        assert_eq!(client.server_version().await, Some((1, 0, 0)));
+        assert!(client.close().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_primary_node_refetches_after_topology_change() -> io::Result<()> {
+        // every partitions request sees a newer topology, with all partitions moved to a node
+        // whose id is the topology version
+        let port = spawn_fake_server(|request_id| {
+            let mut payload = BytesMut::new();
+            payload.put_i64_le(request_id);
+            payload.put_i32_le(0);
+            payload.put_i64_le(request_id + 1);
+            payload.put_i32_le(0);
+            payload.put_i32_le(1);
+            payload.put_u8(1);
+            payload.put_i32_le(1);
+            payload.put_i32_le(42);
+            payload.put_i32_le(0);
+            payload.put_i32_le(1);
+            payload.put_u8(10);
+            payload.put_i64_le(0);
+            payload.put_i64_le(request_id + 1);
+            payload.put_i32_le(1024);
+            for partition in 0..1024 {
+                payload.put_i32_le(partition);
+            }
+            (Duration::ZERO, payload.to_vec())
+        })
+        .await?;
+        let client = fake_server_client(port).await?;
+
+        assert_eq!(client.primary_node(42, &Value::I32(1)).await?, Some(1));
+        // within the refresh interval the cached distribution is used
+        assert_eq!(client.primary_node(42, &Value::I32(1)).await?, Some(1));
+
+        client.set_partition_refresh_interval(Duration::ZERO).await;
+        assert_eq!(client.primary_node(42, &Value::I32(1)).await?, Some(2));
+        assert_eq!(
+            client.topology_version().await,
+            Some(TopologyVersion { major: 2, minor: 0 })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute_batch_client_timeout() -> io::Result<()> {
+        let port = spawn_fake_server(|request_id| {
+            (Duration::from_secs(10), bool_response(request_id, true))
+        })
+        .await?;
+        let client = fake_server_client(port).await?;
+        client.set_timeout_grace(Duration::from_millis(50)).await;
+
+        let mut requests = vec![];
+        for _ in 0..2 {
+            let mut request = sql_fields_request("DELETE FROM T", StatementType::UPDATE);
+            request.timeout_milliseconds = 50;
+            requests.push(request);
+        }
+        let result = client.execute_batch(requests).await;
+        assert!(matches!(result, Err(IgniteError::TimedOut)));
+        assert!(!client.close().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_resets_connection() -> io::Result<()> {
+        // the first request is answered late, by which time the caller has given up on it
+        let port = spawn_fake_server(|request_id| {
+            let delay = if request_id == 0 { 200 } else { 0 };
+            (Duration::from_millis(delay), bool_response(request_id, true))
+        })
+        .await?;
+        let client = fake_server_client(port).await?;
+        let other = client.clone();
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            client.contains_key(1, Value::I32(1)),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        let result = other.contains_key(1, Value::I32(1)).await;
+        assert!(matches!(result, Err(IgniteError::NotHandshaked)));
+        assert!(!other.close().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_id_mismatch_resets_connection() -> io::Result<()> {
+        let port = spawn_fake_server(|request_id| {
+            (Duration::ZERO, bool_response(request_id + 1, true))
+        })
+        .await?;
+        let client = fake_server_client(port).await?;
+
+        let result = client.contains_key(1, Value::I32(1)).await;
+        assert!(matches!(
+            result,
+            Err(IgniteError::RequestIdMismatch {
+                expected: 0,
+                actual: 1
+            })
+        ));
+        let result = client.contains_key(1, Value::I32(1)).await;
+        assert!(matches!(result, Err(IgniteError::NotHandshaked)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_raw_request() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "RAW_REQUEST_TEST").await?;
+        client
+            .put_if_absent(cache_id, Value::I32(1), Value::String("a".to_string()))
+            .await?;
+
+        // OP_CACHE_GET_SIZE with no peek modes
+        let mut body = BytesMut::new();
+        body.put_i32_le(cache_id);
+        body.put_u8(0);
+        body.put_i32_le(0);
+        let payload = client.raw_request(1020, body).await?;
+        assert_eq!(payload.len(), 8 + 4 + 8);
+        assert_eq!(i64::from_le_bytes(payload[12..20].try_into().unwrap()), 1);
+
+        let result = client.raw_request(4242, BytesMut::new()).await;
+        assert!(matches!(
+            result,
+            Err(IgniteError::Server {
+                status: ServerStatus::InvalidOpCode,
+                ..
+            })
+        ));
+
+        client.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_primary_node() -> io::Result<()> {
+        let client = handshaked_client().await?;
+        let cache_id = create_kv_table(&client, "PRIMARY_NODE_TEST").await?;
+
+        let cache_partitions = client.cache_partitions(vec![cache_id]).await?;
+        let mapping = cache_partitions
+            .mappings
+            .iter()
+            .find(|mapping| mapping.cache_ids.contains(&cache_id))
+            .expect("cache is missing from the partition distribution");
+        assert!(mapping.applicable);
+        assert_eq!(
+            client.topology_version().await,
+            Some(cache_partitions.affinity_topology_version)
+        );
+        let owner = client.primary_node(cache_id, &Value::I32(1)).await?;
+        assert!(mapping
+            .node_partitions
+            .iter()
+            .any(|node| Some(node.node_id) == owner));
+        assert_eq!(client.primary_node(cache_id, &Value::Null).await?, None);
+
+        client.close().await?;
+        Ok(())
+    }
+
+    // a server that completes the handshake and answers every request with the delay and
+    // payload returned for its request id
+    async fn spawn_fake_server(
+        respond: impl Fn(i64) -> (Duration, Vec<u8>) + Send + 'static,
+    ) -> io::Result<u16> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut length_buf = [0u8; 4];
+            stream.read_exact(&mut length_buf).await?;
+            let mut handshake = vec![0u8; u32::from_le_bytes(length_buf) as usize];
+            stream.read_exact(&mut handshake).await?;
+            stream.write_all(&[1, 0, 0, 0, 1]).await?;
+            while stream.read_exact(&mut length_buf).await.is_ok() {
+                let mut request = vec![0u8; u32::from_le_bytes(length_buf) as usize];
+                stream.read_exact(&mut request).await?;
+                let request_id = i64::from_le_bytes(request[2..10].try_into().unwrap());
+                let (delay, payload) = respond(request_id);
+                tokio::time::sleep(delay).await;
+                let mut frame = BytesMut::new();
+                frame.put_i32_le(payload.len() as i32);
+                frame.extend_from_slice(&payload);
+                stream.write_all(&frame).await?;
+            }
+            io::Result::Ok(())
+        });
+        Ok(port)
+    }
+
+    async fn fake_server_client(port: u16) -> Result<IgniteClient, IgniteError> {
+        let client = IgniteClient::new("127.0.0.1", port);
+        client
+            .connect_and_handshake(HandshakeRequest::new(
+                1,
+                0,
+                0,
+                "".to_string(),
+                "".to_string(),
+            ))
+            .await?;
+        Ok(client)
+    }
+
+    fn bool_response(request_id: i64, value: bool) -> Vec<u8> {
+        let mut payload = BytesMut::new();
+        payload.put_i64_le(request_id);
+        payload.put_i32_le(0);
+        payload.put_u8(value as u8);
+        payload.to_vec()
+    }
+
+    fn select_schemas_request() -> QuerySqlFieldsRequest {
+        sql_fields_request("SELECT * FROM SYS.SCHEMAS", StatementType::SELECT)
+    }
+
+    fn sql_fields_request(sql: &str, statement_type: StatementType) -> QuerySqlFieldsRequest {
+        QuerySqlFieldsRequest::new(
+            0,
+            "PUBLIC".to_string(),
+            1024,
+            65535,
+            sql.to_string(),
+            Vec::new(),
+            statement_type,
+            false,
+            false,
+            false,
+            false,
+            false,
+            false,
+            30 * 1000,
+            true,
+        )
+    }
+
+    async fn handshaked_client() -> Result<IgniteClient, IgniteError> {
+        let client = IgniteClient::new("127.0.0.1", 10800);
+        client
+            .connect_and_handshake(HandshakeRequest::new(
+                1,
+                0,
+                0,
+                "".to_string(),
+                "".to_string(),
+            ))
+            .await?;
+        Ok(client)
+    }
+
+    // creates an empty INT -> VARCHAR table and returns the id of its cache
+    async fn create_kv_table(client: &IgniteClient, table: &str) -> Result<i32, IgniteError> {
+        let ddl = format!(
+            "CREATE TABLE IF NOT EXISTS {} (ID INT PRIMARY KEY, V VARCHAR) WITH \"WRAP_VALUE=false\"",
+            table
+        );
+        client
+            .query_sql_fields(sql_fields_request(&ddl, StatementType::ANY))
+            .await?;
+        client
+            .query_sql_fields(sql_fields_request(
+                &format!("DELETE FROM {}", table),
+                StatementType::ANY,
+            ))
+            .await?;
+        Ok(cache_id(&format!("SQL_PUBLIC_{}", table)))
+    }
 }
@@ -1,4 +1,8 @@
+pub mod affinity;
+pub mod error;
 pub mod ignite_client;
 mod len;
 mod op_const;
 pub mod protocol;
+mod request_id;
+pub mod value;
@@ -1,5 +1,29 @@
 #![allow(dead_code)]
 
+pub const CACHE_PUT_IF_ABSENT: i16 = 1002;
+pub const CACHE_GET_AND_REMOVE: i16 = 1007;
+pub const CACHE_REPLACE: i16 = 1009;
+pub const CACHE_REPLACE_IF_EQUALS: i16 = 1010;
+pub const CACHE_CONTAINS_KEY: i16 = 1011;
+pub const CACHE_CLEAR: i16 = 1013;
+pub const CACHE_CLEAR_KEY: i16 = 1014;
+pub const CACHE_CLEAR_KEYS: i16 = 1015;
+pub const CACHE_REMOVE_KEY: i16 = 1016;
+pub const CACHE_GET_SIZE: i16 = 1020;
+pub const CACHE_PARTITIONS: i16 = 1101;
 pub const QUERY_SQL: i16 = 2002;
 pub const QUERY_SQL_CURSOR_GET_PAGE: i16 = 2003;
 pub const QUERY_SQL_FIELDS: i16 = 2004;
+
+pub const STATUS_SUCCESS: i32 = 0;
+pub const STATUS_FAILED: i32 = 1;
+pub const STATUS_INVALID_OP_CODE: i32 = 2;
+pub const STATUS_CACHE_DOES_NOT_EXIST: i32 = 1000;
+pub const STATUS_CACHE_EXISTS: i32 = 1001;
+pub const STATUS_TOO_MANY_CURSORS: i32 = 1010;
+pub const STATUS_RESOURCE_DOES_NOT_EXIST: i32 = 1011;
+pub const STATUS_SECURITY_VIOLATION: i32 = 1012;
+pub const STATUS_TX_LIMIT_EXCEEDED: i32 = 1020;
+pub const STATUS_TX_NOT_FOUND: i32 = 1021;
+pub const STATUS_TOO_MANY_COMPUTE_TASKS: i32 = 1030;
+pub const STATUS_AUTH_FAILED: i32 = 2000;
@@ -1,6 +1,6 @@
+use crate::value::{read_bytes, Encode, Value};
 use crate::{len, op_const};
 use bytes::{BufMut, BytesMut};
-use std::any::Any;
 use std::io::{Error, ErrorKind};
 use tokio::io;
 
@@ -37,19 +37,34 @@ impl HandshakeRequest {
         buf.put_i16_le(self.major_version);
         buf.put_i16_le(self.minor_version);
         buf.put_i16_le(self.patch_version);
+        // client code, always 2 for thin clients
         buf.put_u8(2);
-        buf.put_i32_le(self.username.len() as i32);
-        buf.extend_from_slice(self.username.as_bytes());
-        buf.put_i32_le(self.password.len() as i32);
-        buf.extend_from_slice(self.password.as_bytes());
+        if self.has_credentials() {
+            buf.put_u8(9);
+            buf.put_i32_le(self.username.len() as i32);
+            buf.extend_from_slice(self.username.as_bytes());
+            buf.put_u8(9);
+            buf.put_i32_le(self.password.len() as i32);
+            buf.extend_from_slice(self.password.as_bytes());
+        }
         buf
     }
 
     fn length(&self) -> usize {
-        1 + 2 + 2 + 2 + 1 + 4 + self.username.len() + 4 + self.password.len()
+        let mut total_length = 1 + 2 + 2 + 2 + 1;
+        if self.has_credentials() {
+            total_length += len::str(&self.username) + len::str(&self.password);
+        }
+        total_length
+    }
+
+    // username and password are only sent when credentials are configured
+    fn has_credentials(&self) -> bool {
+        !self.username.is_empty() || !self.password.is_empty()
     }
 }
 
+#[derive(Clone)]
 pub enum HandshakeResponse {
     Success,
     Failure {
@@ -63,19 +78,23 @@ pub enum HandshakeResponse {
 impl HandshakeResponse {
     pub(crate) fn decode(data: &[u8]) -> io::Result<Self> {
         if data.is_empty() {
-            return Err(Error::new(ErrorKind::Other, "Empty response"));
+            return Err(Error::other("Empty response"));
         }
 
         let success_flag = data[0];
         if success_flag == 1 {
             Ok(HandshakeResponse::Success)
         } else {
-            let major_version = i16::from_le_bytes([data[1], data[2]]);
-            let minor_version = i16::from_le_bytes([data[3], data[4]]);
-            let patch_version = i16::from_le_bytes([data[5], data[6]]);
+            let mut offset = 1;
+            let major_version = read_i16(data, &mut offset)?;
+            let minor_version = read_i16(data, &mut offset)?;
+            let patch_version = read_i16(data, &mut offset)?;
 
-            let error_message = String::from_utf8(data[7..].to_vec())
-                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            // a typed string, newer servers follow it with a status code
+            let error_message = match Value::decode(data, offset)?.0 {
+                Value::String(error_message) => error_message,
+                _ => String::new(),
+            };
 
             Ok(HandshakeResponse::Failure {
                 major_version,
@@ -96,6 +115,14 @@ pub struct Request {
 pub enum RequestType {
     QuerySql(QuerySqlRequest),
     QuerySqlFields(QuerySqlFieldsRequest),
+    Cache(CacheRequest),
+    CacheKey(CacheKeyRequest),
+    CacheKeys(CacheKeysRequest),
+    CacheKeyValue(CacheKeyValueRequest),
+    CacheReplaceIfEquals(CacheReplaceIfEqualsRequest),
+    CacheGetSize(CacheGetSizeRequest),
+    CachePartitions(CachePartitionsRequest),
+    Raw(BytesMut),
 }
 
 impl Request {
@@ -117,10 +144,121 @@ impl Request {
             body: RequestType::QuerySqlFields(query_sql_fields_request),
         }
     }
+
+    pub fn new_raw(request_id: i64, op_code: i16, body: BytesMut) -> Request {
+        Request {
+            op_code,
+            request_id,
+            body: RequestType::Raw(body),
+        }
+    }
+
+    pub fn new_cache_clear(request_id: i64, cache_request: CacheRequest) -> Request {
+        Request {
+            op_code: op_const::CACHE_CLEAR,
+            request_id,
+            body: RequestType::Cache(cache_request),
+        }
+    }
+
+    pub fn new_cache_clear_key(request_id: i64, cache_key_request: CacheKeyRequest) -> Request {
+        Request {
+            op_code: op_const::CACHE_CLEAR_KEY,
+            request_id,
+            body: RequestType::CacheKey(cache_key_request),
+        }
+    }
+
+    pub fn new_cache_clear_keys(request_id: i64, cache_keys_request: CacheKeysRequest) -> Request {
+        Request {
+            op_code: op_const::CACHE_CLEAR_KEYS,
+            request_id,
+            body: RequestType::CacheKeys(cache_keys_request),
+        }
+    }
+
+    pub fn new_cache_contains_key(request_id: i64, cache_key_request: CacheKeyRequest) -> Request {
+        Request {
+            op_code: op_const::CACHE_CONTAINS_KEY,
+            request_id,
+            body: RequestType::CacheKey(cache_key_request),
+        }
+    }
+
+    pub fn new_cache_put_if_absent(
+        request_id: i64,
+        cache_key_value_request: CacheKeyValueRequest,
+    ) -> Request {
+        Request {
+            op_code: op_const::CACHE_PUT_IF_ABSENT,
+            request_id,
+            body: RequestType::CacheKeyValue(cache_key_value_request),
+        }
+    }
+
+    pub fn new_cache_replace(
+        request_id: i64,
+        cache_key_value_request: CacheKeyValueRequest,
+    ) -> Request {
+        Request {
+            op_code: op_const::CACHE_REPLACE,
+            request_id,
+            body: RequestType::CacheKeyValue(cache_key_value_request),
+        }
+    }
+
+    pub fn new_cache_replace_if_equals(
+        request_id: i64,
+        cache_replace_if_equals_request: CacheReplaceIfEqualsRequest,
+    ) -> Request {
+        Request {
+            op_code: op_const::CACHE_REPLACE_IF_EQUALS,
+            request_id,
+            body: RequestType::CacheReplaceIfEquals(cache_replace_if_equals_request),
+        }
+    }
+
+    pub fn new_cache_get_and_remove(request_id: i64, cache_key_request: CacheKeyRequest) -> Request {
+        Request {
+            op_code: op_const::CACHE_GET_AND_REMOVE,
+            request_id,
+            body: RequestType::CacheKey(cache_key_request),
+        }
+    }
+
+    pub fn new_cache_remove_key(request_id: i64, cache_key_request: CacheKeyRequest) -> Request {
+        Request {
+            op_code: op_const::CACHE_REMOVE_KEY,
+            request_id,
+            body: RequestType::CacheKey(cache_key_request),
+        }
+    }
+
+    pub fn new_cache_get_size(
+        request_id: i64,
+        cache_get_size_request: CacheGetSizeRequest,
+    ) -> Request {
+        Request {
+            op_code: op_const::CACHE_GET_SIZE,
+            request_id,
+            body: RequestType::CacheGetSize(cache_get_size_request),
+        }
+    }
+
+    pub fn new_cache_partitions(
+        request_id: i64,
+        cache_partitions_request: CachePartitionsRequest,
+    ) -> Request {
+        Request {
+            op_code: op_const::CACHE_PARTITIONS,
+            request_id,
+            body: RequestType::CachePartitions(cache_partitions_request),
+        }
+    }
 }
 
 impl Request {
-    pub(crate) fn encode(&self) -> BytesMut {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
         let payload_length = self.length();
         let mut buf = BytesMut::with_capacity(payload_length + 4);
         buf.put_i32_le(payload_length as i32);
@@ -128,13 +266,37 @@ impl Request {
         buf.put_i64_le(self.request_id);
         match &self.body {
             RequestType::QuerySql(query_sql_request) => {
-                buf.extend_from_slice(&query_sql_request.encode());
+                buf.extend_from_slice(&query_sql_request.encode()?);
             }
             RequestType::QuerySqlFields(query_sql_fields_request) => {
-                buf.extend_from_slice(&query_sql_fields_request.encode());
+                buf.extend_from_slice(&query_sql_fields_request.encode()?);
+            }
+            RequestType::Cache(cache_request) => {
+                buf.extend_from_slice(&cache_request.encode());
+            }
+            RequestType::CacheKey(cache_key_request) => {
+                buf.extend_from_slice(&cache_key_request.encode()?);
+            }
+            RequestType::CacheKeys(cache_keys_request) => {
+                buf.extend_from_slice(&cache_keys_request.encode()?);
+            }
+            RequestType::CacheKeyValue(cache_key_value_request) => {
+                buf.extend_from_slice(&cache_key_value_request.encode()?);
+            }
+            RequestType::CacheReplaceIfEquals(cache_replace_if_equals_request) => {
+                buf.extend_from_slice(&cache_replace_if_equals_request.encode()?);
+            }
+            RequestType::CacheGetSize(cache_get_size_request) => {
+                buf.extend_from_slice(&cache_get_size_request.encode());
+            }
+            RequestType::CachePartitions(cache_partitions_request) => {
+                buf.extend_from_slice(&cache_partitions_request.encode());
+            }
+            RequestType::Raw(body) => {
+                buf.extend_from_slice(body);
             }
         }
-        buf
+        Ok(buf)
     }
 
     fn length(&self) -> usize {
@@ -144,6 +306,18 @@ impl Request {
             RequestType::QuerySqlFields(query_sql_fields_request) => {
                 query_sql_fields_request.length()
             }
+            RequestType::Cache(cache_request) => cache_request.length(),
+            RequestType::CacheKey(cache_key_request) => cache_key_request.length(),
+            RequestType::CacheKeys(cache_keys_request) => cache_keys_request.length(),
+            RequestType::CacheKeyValue(cache_key_value_request) => cache_key_value_request.length(),
+            RequestType::CacheReplaceIfEquals(cache_replace_if_equals_request) => {
+                cache_replace_if_equals_request.length()
+            }
+            RequestType::CacheGetSize(cache_get_size_request) => cache_get_size_request.length(),
+            RequestType::CachePartitions(cache_partitions_request) => {
+                cache_partitions_request.length()
+            }
+            RequestType::Raw(body) => body.len(),
         }
     }
 }
@@ -158,12 +332,18 @@ pub struct Response {
 pub enum ResponseType {
     QuerySql(QuerySqlResponse),
     QuerySqlFields(QuerySqlFieldsResponse),
+    Empty,
+    Bool(bool),
+    I64(i64),
+    Value(Value),
+    CachePartitions(CachePartitionsResponse),
+    Raw(Vec<u8>),
 }
 
 impl Response {
     pub(crate) fn decode_query_sql(data: &[u8]) -> io::Result<Self> {
         if data.is_empty() {
-            return Err(Error::new(ErrorKind::Other, "Empty response"));
+            return Err(Error::other("Empty response"));
         }
 
         let request_id = i64::from_le_bytes([
@@ -198,50 +378,476 @@ impl Response {
     }
 
     pub(crate) fn decode_query_sql_fields(data: &[u8], includes_field_names: bool) -> io::Result<Self> {
-        if data.is_empty() {
-            return Err(Error::new(ErrorKind::Other, "Empty response"));
-        }
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        let query_sql_fields_response = if status_code == 0 {
+            QuerySqlFieldsResponse::decode(&data[12..], includes_field_names)?
+        } else {
+            QuerySqlFieldsResponse {
+                cursor_id: 0,
+                column_count: 0,
+                column_names: vec![],
+                first_page_row_count: 0,
+                rows: vec![],
+                has_more: false,
+            }
+        };
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::QuerySqlFields(query_sql_fields_response),
+        })
+    }
 
-        let request_id = i64::from_le_bytes([
-            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-        ]);
-        let status_code = i32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-        if status_code != 0 {
-            // skip the string type code
-            let error_message_length = i32::from_le_bytes([data[13], data[14], data[15], data[16]]);
-            let error_message =
-                String::from_utf8(data[17..(17 + error_message_length as usize)].to_vec())
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-            Ok(Response {
-                request_id,
-                status_code,
-                error_message,
-                body: ResponseType::QuerySqlFields(QuerySqlFieldsResponse {
-                    cursor_id: 0,
-                    column_count: 0,
-                    column_names: vec![],
-                    first_page_row_count: 0,
-                    has_more: false,
-                }),
-            })
+    // for ops whose response carries nothing but the status
+    pub(crate) fn decode_empty(data: &[u8]) -> io::Result<Self> {
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::Empty,
+        })
+    }
+
+    pub(crate) fn decode_bool(data: &[u8]) -> io::Result<Self> {
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        let value = status_code == 0 && read_bytes(data, &mut 12, 1)?[0] == 1;
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::Bool(value),
+        })
+    }
+
+    pub(crate) fn decode_i64(data: &[u8]) -> io::Result<Self> {
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        let value = if status_code == 0 {
+            i64::from_le_bytes(read_bytes(data, &mut 12, 8)?.try_into().unwrap())
         } else {
-            let query_sql_fields_response = QuerySqlFieldsResponse::decode(&data[12..], includes_field_names)?;
-            Ok(Response {
-                request_id,
-                status_code,
-                error_message: String::new(),
-                body: ResponseType::QuerySqlFields(query_sql_fields_response),
-            })
+            0
+        };
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::I64(value),
+        })
+    }
+
+    pub(crate) fn decode_raw(data: &[u8]) -> io::Result<Self> {
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::Raw(data.to_vec()),
+        })
+    }
+
+    pub(crate) fn decode_value(data: &[u8]) -> io::Result<Self> {
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        let value = if status_code == 0 {
+            Value::decode(data, 12)?.0
+        } else {
+            Value::Null
+        };
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::Value(value),
+        })
+    }
+
+    pub(crate) fn decode_cache_partitions(data: &[u8]) -> io::Result<Self> {
+        let (request_id, status_code, error_message) = decode_header(data)?;
+        let cache_partitions = if status_code == 0 {
+            CachePartitionsResponse::decode(&data[12..])?
+        } else {
+            CachePartitionsResponse {
+                affinity_topology_version: TopologyVersion { major: 0, minor: 0 },
+                mappings: vec![],
+            }
+        };
+        Ok(Response {
+            request_id,
+            status_code,
+            error_message,
+            body: ResponseType::CachePartitions(cache_partitions),
+        })
+    }
+}
+
+// decodes the request id, status code and error message shared by all responses
+fn decode_header(data: &[u8]) -> io::Result<(i64, i32, String)> {
+    let mut offset = 0;
+    let request_id = i64::from_le_bytes(read_bytes(data, &mut offset, 8)?.try_into().unwrap());
+    let status_code = i32::from_le_bytes(read_bytes(data, &mut offset, 4)?.try_into().unwrap());
+    if status_code == 0 {
+        return Ok((request_id, status_code, String::new()));
+    }
+    let error_message = match Value::decode(data, offset)?.0 {
+        Value::String(error_message) => error_message,
+        _ => String::new(),
+    };
+    Ok((request_id, status_code, error_message))
+}
+
+pub struct CacheRequest {
+    pub cache_id: i32,
+}
+
+impl CacheRequest {
+    pub fn new(cache_id: i32) -> CacheRequest {
+        CacheRequest { cache_id }
+    }
+}
+
+impl CacheRequest {
+    pub(crate) fn encode(&self) -> BytesMut {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_id);
+        buf.put_u8(0);
+        buf
+    }
+
+    fn length(&self) -> usize {
+        len::CACHE_ID + 1
+    }
+}
+
+pub struct CacheKeyRequest {
+    pub cache_id: i32,
+    pub key: Value,
+}
+
+impl CacheKeyRequest {
+    pub fn new(cache_id: i32, key: Value) -> CacheKeyRequest {
+        CacheKeyRequest { cache_id, key }
+    }
+}
+
+impl CacheKeyRequest {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_id);
+        buf.put_u8(0);
+        self.key.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn length(&self) -> usize {
+        let mut total_length: usize = 0;
+        total_length += len::CACHE_ID;
+        total_length += 1;
+        total_length += self.key.length();
+        total_length
+    }
+}
+
+pub struct CacheKeysRequest {
+    pub cache_id: i32,
+    pub keys: Vec<Value>,
+}
+
+impl CacheKeysRequest {
+    pub fn new(cache_id: i32, keys: Vec<Value>) -> CacheKeysRequest {
+        CacheKeysRequest { cache_id, keys }
+    }
+}
+
+impl CacheKeysRequest {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_id);
+        buf.put_u8(0);
+        buf.put_i32_le(self.keys.len() as i32);
+        for key in &self.keys {
+            key.encode(&mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    fn length(&self) -> usize {
+        let mut total_length: usize = 0;
+        total_length += len::CACHE_ID;
+        total_length += 1;
+        total_length += len::KEY_COUNT;
+        total_length += self.keys.iter().map(Value::length).sum::<usize>();
+        total_length
+    }
+}
+
+pub struct CacheKeyValueRequest {
+    pub cache_id: i32,
+    pub key: Value,
+    pub value: Value,
+}
+
+impl CacheKeyValueRequest {
+    pub fn new(cache_id: i32, key: Value, value: Value) -> CacheKeyValueRequest {
+        CacheKeyValueRequest {
+            cache_id,
+            key,
+            value,
+        }
+    }
+}
+
+impl CacheKeyValueRequest {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_id);
+        buf.put_u8(0);
+        self.key.encode(&mut buf)?;
+        self.value.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn length(&self) -> usize {
+        let mut total_length: usize = 0;
+        total_length += len::CACHE_ID;
+        total_length += 1;
+        total_length += self.key.length();
+        total_length += self.value.length();
+        total_length
+    }
+}
+
+pub struct CacheReplaceIfEqualsRequest {
+    pub cache_id: i32,
+    pub key: Value,
+    pub old_value: Value,
+    pub new_value: Value,
+}
+
+impl CacheReplaceIfEqualsRequest {
+    pub fn new(
+        cache_id: i32,
+        key: Value,
+        old_value: Value,
+        new_value: Value,
+    ) -> CacheReplaceIfEqualsRequest {
+        CacheReplaceIfEqualsRequest {
+            cache_id,
+            key,
+            old_value,
+            new_value,
+        }
+    }
+}
+
+impl CacheReplaceIfEqualsRequest {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_id);
+        buf.put_u8(0);
+        self.key.encode(&mut buf)?;
+        self.old_value.encode(&mut buf)?;
+        self.new_value.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn length(&self) -> usize {
+        let mut total_length: usize = 0;
+        total_length += len::CACHE_ID;
+        total_length += 1;
+        total_length += self.key.length();
+        total_length += self.old_value.length();
+        total_length += self.new_value.length();
+        total_length
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone)]
+pub enum PeekMode {
+    ALL = 0,
+    NEAR = 1,
+    PRIMARY = 2,
+    BACKUP = 3,
+}
+
+pub struct CacheGetSizeRequest {
+    pub cache_id: i32,
+    pub peek_modes: Vec<PeekMode>,
+}
+
+impl CacheGetSizeRequest {
+    pub fn new(cache_id: i32, peek_modes: Vec<PeekMode>) -> CacheGetSizeRequest {
+        CacheGetSizeRequest {
+            cache_id,
+            peek_modes,
         }
     }
 }
 
+impl CacheGetSizeRequest {
+    pub(crate) fn encode(&self) -> BytesMut {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_id);
+        buf.put_u8(0);
+        // an empty list of peek modes counts all entries
+        buf.put_i32_le(self.peek_modes.len() as i32);
+        for peek_mode in &self.peek_modes {
+            buf.put_u8(*peek_mode as u8);
+        }
+        buf
+    }
+
+    fn length(&self) -> usize {
+        let mut total_length: usize = 0;
+        total_length += len::CACHE_ID;
+        total_length += 1;
+        total_length += len::PEEK_MODE_COUNT;
+        total_length += len::PEEK_MODE * self.peek_modes.len();
+        total_length
+    }
+}
+
+pub struct CachePartitionsRequest {
+    pub cache_ids: Vec<i32>,
+}
+
+impl CachePartitionsRequest {
+    pub fn new(cache_ids: Vec<i32>) -> CachePartitionsRequest {
+        CachePartitionsRequest { cache_ids }
+    }
+}
+
+impl CachePartitionsRequest {
+    pub(crate) fn encode(&self) -> BytesMut {
+        let payload_length = self.length();
+        let mut buf = BytesMut::with_capacity(payload_length);
+        buf.put_i32_le(self.cache_ids.len() as i32);
+        for cache_id in &self.cache_ids {
+            buf.put_i32_le(*cache_id);
+        }
+        buf
+    }
+
+    fn length(&self) -> usize {
+        len::CACHE_ID_COUNT + len::CACHE_ID * self.cache_ids.len()
+    }
+}
+
+// the affinity topology version, bumped by the server whenever partitions move between nodes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TopologyVersion {
+    pub major: i64,
+    pub minor: i32,
+}
+
+pub struct CachePartitionsResponse {
+    pub affinity_topology_version: TopologyVersion,
+    pub mappings: Vec<PartitionMapping>,
+}
+
+// caches sharing the same affinity function and partition distribution
+pub struct PartitionMapping {
+    // false when the partitions can't be computed on the client, e.g. a custom affinity function
+    pub applicable: bool,
+    pub cache_ids: Vec<i32>,
+    pub node_partitions: Vec<NodePartitions>,
+}
+
+// the primary partitions of a node, node ids are UUIDs
+pub struct NodePartitions {
+    pub node_id: u128,
+    pub partitions: Vec<i32>,
+}
+
+impl CachePartitionsResponse {
+    pub(crate) fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut offset = 0;
+        let affinity_topology_version = TopologyVersion {
+            major: read_i64(data, &mut offset)?,
+            minor: read_i32(data, &mut offset)?,
+        };
+        let mapping_count = read_i32(data, &mut offset)?;
+        let mut mappings = Vec::with_capacity(capacity(mapping_count, data.len() - offset));
+        for _ in 0..mapping_count {
+            let applicable = read_bytes(data, &mut offset, 1)?[0] == 1;
+            let cache_count = read_i32(data, &mut offset)?;
+            let mut cache_ids = Vec::with_capacity(capacity(cache_count, data.len() - offset));
+            for _ in 0..cache_count {
+                cache_ids.push(read_i32(data, &mut offset)?);
+                if applicable {
+                    // affinity key fields of binary object keys, which the client doesn't support
+                    let key_config_count = read_i32(data, &mut offset)?;
+                    read_bytes(data, &mut offset, 8 * key_config_count.max(0) as usize)?;
+                }
+            }
+            let mut node_partitions = vec![];
+            if applicable {
+                let node_count = read_i32(data, &mut offset)?;
+                for _ in 0..node_count {
+                    // a UUID is sent as its type code followed by the most and least significant halves
+                    read_bytes(data, &mut offset, 1)?;
+                    let most_significant = read_i64(data, &mut offset)? as u64;
+                    let least_significant = read_i64(data, &mut offset)? as u64;
+                    let partition_count = read_i32(data, &mut offset)?;
+                    let mut partitions =
+                        Vec::with_capacity(capacity(partition_count, data.len() - offset));
+                    for _ in 0..partition_count {
+                        partitions.push(read_i32(data, &mut offset)?);
+                    }
+                    node_partitions.push(NodePartitions {
+                        node_id: ((most_significant as u128) << 64) | least_significant as u128,
+                        partitions,
+                    });
+                }
+            }
+            mappings.push(PartitionMapping {
+                applicable,
+                cache_ids,
+                node_partitions,
+            });
+        }
+        Ok(CachePartitionsResponse {
+            affinity_topology_version,
+            mappings,
+        })
+    }
+}
+
+// the capacity to reserve for count items read from the wire, each taking at least a byte
+fn capacity(count: i32, remaining: usize) -> usize {
+    (count.max(0) as usize).min(remaining)
+}
+
+fn read_i16(data: &[u8], offset: &mut usize) -> io::Result<i16> {
+    Ok(i16::from_le_bytes(read_bytes(data, offset, 2)?.try_into().unwrap()))
+}
+
+fn read_i32(data: &[u8], offset: &mut usize) -> io::Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(data, offset, 4)?.try_into().unwrap()))
+}
+
+fn read_i64(data: &[u8], offset: &mut usize) -> io::Result<i64> {
+    Ok(i64::from_le_bytes(read_bytes(data, offset, 8)?.try_into().unwrap()))
+}
+
+// cache ids are the java hash code of the cache name
+pub fn cache_id(cache_name: &str) -> i32 {
+    cache_name
+        .encode_utf16()
+        .fold(0i32, |hash, unit| hash.wrapping_mul(31).wrapping_add(unit as i32))
+}
+
 pub struct QuerySqlRequest {
     pub cache_id: i32,
     pub table: String,
     pub sql: String,
-    pub query_arg_count: i32,
-    pub query_args: Vec<Box<dyn Any>>,
+    pub query_args: Vec<Value>,
     pub distributed_join: bool,
     pub local_query: bool,
     pub replicated_only: bool,
@@ -250,12 +856,12 @@ pub struct QuerySqlRequest {
 }
 
 impl QuerySqlRequest {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         cache_id: i32,
         table: String,
         sql: String,
-        query_arg_count: i32,
-        query_args: Vec<Box<dyn Any>>,
+        query_args: Vec<Value>,
         distributed_join: bool,
         local_query: bool,
         replicated_only: bool,
@@ -266,7 +872,6 @@ impl QuerySqlRequest {
             cache_id,
             table,
             sql,
-            query_arg_count,
             query_args,
             distributed_join,
             local_query,
@@ -278,7 +883,15 @@ impl QuerySqlRequest {
 }
 
 impl QuerySqlRequest {
-    pub(crate) fn encode(&self) -> BytesMut {
+    // appends a query arg, e.g. request.arg(5).arg("foo").arg(true)
+    pub fn arg(mut self, arg: impl Into<Value>) -> Self {
+        self.query_args.push(arg.into());
+        self
+    }
+}
+
+impl QuerySqlRequest {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
         let payload_length = self.length();
         let mut buf = BytesMut::with_capacity(payload_length);
         buf.put_i32_le(self.cache_id);
@@ -289,14 +902,16 @@ impl QuerySqlRequest {
         buf.put_u8(9);
         buf.put_i32_le(self.sql.len() as i32);
         buf.extend_from_slice(self.sql.as_bytes());
-        buf.put_i32_le(self.query_arg_count);
-        // todo args
+        buf.put_i32_le(self.query_args.len() as i32);
+        for query_arg in &self.query_args {
+            query_arg.encode(&mut buf)?;
+        }
         buf.put_u8(self.distributed_join as u8);
         buf.put_u8(self.local_query as u8);
         buf.put_u8(self.replicated_only as u8);
         buf.put_i32_le(self.cursor_page_size);
         buf.put_i64_le(self.timeout_milliseconds);
-        buf
+        Ok(buf)
     }
 
     fn length(&self) -> usize {
@@ -306,6 +921,7 @@ impl QuerySqlRequest {
         total_length += len::str(&self.table);
         total_length += len::str(&self.sql);
         total_length += len::QUERY_ARG_COUNT;
+        total_length += self.query_args.iter().map(Value::length).sum::<usize>();
         total_length += len::DISTRIBUTED_JOIN;
         total_length += len::LOCAL_QUERY;
         total_length += len::REPLICATED_ONLY;
@@ -324,7 +940,7 @@ pub struct QuerySqlResponse {
 impl QuerySqlResponse {
     pub(crate) fn decode(data: &[u8]) -> io::Result<Self> {
         if data.is_empty() {
-            return Err(Error::new(ErrorKind::Other, "Empty response"));
+            return Err(Error::other("Empty response"));
         }
 
         let cursor_id = i64::from_le_bytes([
@@ -346,8 +962,7 @@ pub struct QuerySqlFieldsRequest {
     pub cursor_page_size: i32,
     pub max_rows: i32,
     pub sql: String,
-    pub query_arg_count: i32,
-    pub query_args: Vec<Box<dyn Any>>,
+    pub query_args: Vec<Value>,
     pub statement_type: StatementType,
     pub distributed_join: bool,
     pub local_query: bool,
@@ -368,14 +983,14 @@ pub enum StatementType {
 }
 
 impl QuerySqlFieldsRequest {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         cache_id: i32,
         schema: String,
         cursor_page_size: i32,
         max_rows: i32,
         sql: String,
-        query_arg_count: i32,
-        query_args: Vec<Box<dyn Any>>,
+        query_args: Vec<Value>,
         statement_type: StatementType,
         distributed_join: bool,
         local_query: bool,
@@ -392,7 +1007,6 @@ impl QuerySqlFieldsRequest {
             cursor_page_size,
             max_rows,
             sql,
-            query_arg_count,
             query_args,
             statement_type,
             distributed_join,
@@ -408,7 +1022,15 @@ impl QuerySqlFieldsRequest {
 }
 
 impl QuerySqlFieldsRequest {
-    pub(crate) fn encode(&self) -> BytesMut {
+    // appends a query arg, e.g. request.arg(5).arg("foo").arg(true)
+    pub fn arg(mut self, arg: impl Into<Value>) -> Self {
+        self.query_args.push(arg.into());
+        self
+    }
+}
+
+impl QuerySqlFieldsRequest {
+    pub(crate) fn encode(&self) -> io::Result<BytesMut> {
         let payload_length = self.length();
         let mut buf = BytesMut::with_capacity(payload_length);
         buf.put_i32_le(self.cache_id);
@@ -421,8 +1043,10 @@ impl QuerySqlFieldsRequest {
         buf.put_u8(9);
         buf.put_i32_le(self.sql.len() as i32);
         buf.extend_from_slice(self.sql.as_bytes());
-        buf.put_i32_le(self.query_arg_count);
-        // todo args
+        buf.put_i32_le(self.query_args.len() as i32);
+        for query_arg in &self.query_args {
+            query_arg.encode(&mut buf)?;
+        }
         buf.put_u8(self.statement_type as u8);
         buf.put_u8(self.distributed_join as u8);
         buf.put_u8(self.local_query as u8);
@@ -432,7 +1056,7 @@ impl QuerySqlFieldsRequest {
         buf.put_u8(self.lazy as u8);
         buf.put_i64_le(self.timeout_milliseconds);
         buf.put_u8(self.include_field_names as u8);
-        buf
+        Ok(buf)
     }
 
     fn length(&self) -> usize {
@@ -444,6 +1068,7 @@ impl QuerySqlFieldsRequest {
         total_length += len::MAX_ROWS;
         total_length += len::str(&self.sql);
         total_length += len::QUERY_ARG_COUNT;
+        total_length += self.query_args.iter().map(Value::length).sum::<usize>();
         total_length += len::STATEMENT_TYPE;
         total_length += len::DISTRIBUTED_JOIN;
         total_length += len::LOCAL_QUERY;
@@ -462,62 +1087,232 @@ pub struct QuerySqlFieldsResponse {
     pub column_count: i32,
     pub column_names: Vec<String>,
     pub first_page_row_count: i32,
+    pub rows: Vec<Vec<Value>>,
     pub has_more: bool,
 }
 
 impl QuerySqlFieldsResponse {
     pub(crate) fn decode(data: &[u8], has_field_names: bool) -> io::Result<Self> {
         if data.is_empty() {
-            return Err(Error::new(ErrorKind::Other, "Empty response"));
+            return Err(Error::other("Empty response"));
         }
 
-        let cursor_id = i64::from_le_bytes([
-            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-        ]);
-        let column_count = i32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+        let mut offset = 0;
+        let cursor_id = read_i64(data, &mut offset)?;
+        let column_count = read_i32(data, &mut offset)?;
+        let mut column_names = vec![];
         if has_field_names {
-            let mut column_names = vec![];
-            let mut offset = 12;
             for _ in 0..column_count {
-                offset += 1;
-                let column_name_length = i32::from_le_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]);
-                offset += 4;
-                let column_name = String::from_utf8(
-                    data[offset..(offset + column_name_length as usize)].to_vec(),
-                )
-                    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}", e)))?;
-                column_names.push(column_name);
-                offset += column_name_length as usize;
+                let (column_name, consumed) = Value::decode(data, offset)?;
+                match column_name {
+                    Value::String(column_name) => column_names.push(column_name),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "Column name is not a string",
+                        ))
+                    }
+                }
+                offset += consumed;
             }
-            let first_page_row_count = i32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
-            let has_more = data[offset + 4] == 1;
-            Ok(QuerySqlFieldsResponse {
-                cursor_id,
-                column_count,
-                column_names,
-                first_page_row_count,
-                has_more,
-            })
-        } else {
-            let first_page_row_count = i32::from_le_bytes([data[12], data[13], data[14], data[15]]);
-            let has_more = data[16] == 1;
-            Ok(QuerySqlFieldsResponse {
-                cursor_id,
-                column_count,
-                column_names: vec![],
-                first_page_row_count,
-                has_more,
-            })
+        }
+        let first_page_row_count = read_i32(data, &mut offset)?;
+        // every cell takes at least a byte, so rows without columns can only come from a
+        // corrupt frame and would otherwise be collected without consuming any data
+        if column_count <= 0 && first_page_row_count > 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Rows without columns"));
+        }
+        // counts come off the wire, never reserve more than the remaining bytes could hold
+        let mut rows = Vec::with_capacity(capacity(first_page_row_count, data.len() - offset));
+        for _ in 0..first_page_row_count {
+            let mut row = Vec::with_capacity(capacity(column_count, data.len() - offset));
+            for _ in 0..column_count {
+                let (value, consumed) = Value::decode(data, offset)?;
+                row.push(value);
+                offset += consumed;
+            }
+            rows.push(row);
+        }
+        let has_more = read_bytes(data, &mut offset, 1)?[0] == 1;
+        Ok(QuerySqlFieldsResponse {
+            cursor_id,
+            column_count,
+            column_names,
+            first_page_row_count,
+            rows,
+            has_more,
+        })
+    }
+}
+
+impl QuerySqlFieldsResponse {
+    // DML statements return a single row with a single column holding the affected row count
+    pub fn affected_rows(&self) -> Option<i64> {
+        match self.rows.as_slice() {
+            [row] => match row.as_slice() {
+                [Value::I64(affected_rows)] => Some(*affected_rows),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_id() {
+        assert_eq!(cache_id(""), 0);
+        assert_eq!(cache_id("PUBLIC"), -1924094359);
+        assert_eq!(cache_id("SQL_PUBLIC_PERSON"), -1447683814);
+    }
+
+    #[test]
+    fn test_encode_handshake_without_credentials() {
+        let request = HandshakeRequest::new(1, 0, 0, String::new(), String::new());
+        let buf = request.encode();
+        assert_eq!(&buf[..], &[8, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_encode_handshake_with_credentials() {
+        let request = HandshakeRequest::new(1, 1, 0, "ignite".to_string(), "pw".to_string());
+        let buf = request.encode();
+        assert_eq!(buf.len(), 4 + request.length());
+        assert_eq!(i32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize, request.length());
+        assert_eq!(buf[11], 2);
+        assert_eq!(
+            Value::decode(&buf, 12).unwrap(),
+            (Value::String("ignite".to_string()), 11)
+        );
+        assert_eq!(Value::decode(&buf, 23).unwrap(), (Value::String("pw".to_string()), 7));
+    }
+
+    #[test]
+    fn test_decode_handshake_failure() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(0);
+        buf.put_i16_le(1);
+        buf.put_i16_le(7);
+        buf.put_i16_le(0);
+        Value::String("Unsupported version.".to_string()).encode(&mut buf).unwrap();
+        buf.put_i32_le(1);
+
+        match HandshakeResponse::decode(&buf).unwrap() {
+            HandshakeResponse::Failure {
+                major_version,
+                minor_version,
+                error_message,
+                ..
+            } => {
+                assert_eq!((major_version, minor_version), (1, 7));
+                assert_eq!(error_message, "Unsupported version.");
+            }
+            HandshakeResponse::Success => panic!("expected a handshake failure"),
+        }
+    }
+
+    #[test]
+    fn test_decode_dml_response() {
+        let mut buf = BytesMut::new();
+        buf.put_i64_le(1);
+        buf.put_i32_le(1);
+        Value::String("UPDATED".to_string()).encode(&mut buf).unwrap();
+        buf.put_i32_le(1);
+        Value::I64(3).encode(&mut buf).unwrap();
+        buf.put_u8(0);
+
+        let response = QuerySqlFieldsResponse::decode(&buf, true).unwrap();
+        assert_eq!(response.column_names, vec!["UPDATED".to_string()]);
+        assert_eq!(response.affected_rows(), Some(3));
+        assert!(!response.has_more);
+    }
+
+    #[test]
+    fn test_decode_truncated_sql_fields_response() {
+        let mut buf = BytesMut::new();
+        buf.put_i64_le(1);
+        buf.put_i32_le(1);
+        Value::String("UPDATED".to_string()).encode(&mut buf).unwrap();
+        buf.put_i32_le(1);
+        Value::I64(3).encode(&mut buf).unwrap();
+
+        for length in 0..buf.len() {
+            assert!(QuerySqlFieldsResponse::decode(&buf[..length], true).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_sql_fields_response_huge_row_count() {
+        for column_count in [1, 0] {
+            let mut buf = BytesMut::new();
+            buf.put_i64_le(1);
+            buf.put_i32_le(column_count);
+            buf.put_i32_le(i32::MAX);
+            buf.put_u8(0);
+            assert!(QuerySqlFieldsResponse::decode(&buf, false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_cache_partitions_response() {
+        let mut buf = BytesMut::new();
+        buf.put_i64_le(3);
+        buf.put_i32_le(1);
+        buf.put_i32_le(2);
+        // applicable mapping with one cache and no key configs
+        buf.put_u8(1);
+        buf.put_i32_le(1);
+        buf.put_i32_le(42);
+        buf.put_i32_le(0);
+        buf.put_i32_le(1);
+        buf.put_u8(10);
+        buf.put_i64_le(1);
+        buf.put_i64_le(2);
+        buf.put_i32_le(2);
+        buf.put_i32_le(0);
+        buf.put_i32_le(1);
+        // mapping with a custom affinity function
+        buf.put_u8(0);
+        buf.put_i32_le(1);
+        buf.put_i32_le(43);
+
+        let response = CachePartitionsResponse::decode(&buf).unwrap();
+        assert_eq!(
+            response.affinity_topology_version,
+            TopologyVersion { major: 3, minor: 1 }
+        );
+        assert_eq!(response.mappings.len(), 2);
+        assert!(response.mappings[0].applicable);
+        assert_eq!(response.mappings[0].cache_ids, vec![42]);
+        assert_eq!(response.mappings[0].node_partitions[0].node_id, (1 << 64) | 2);
+        assert_eq!(response.mappings[0].node_partitions[0].partitions, vec![0, 1]);
+        assert!(!response.mappings[1].applicable);
+        assert!(response.mappings[1].node_partitions.is_empty());
+    }
+
+    #[test]
+    fn test_decode_cache_partitions_response_huge_counts() {
+        // the mapping, cache and partition counts in turn claim more entries than were sent
+        for huge in 0..3 {
+            let count = |index| if index == huge { i32::MAX } else { 1 };
+            let mut buf = BytesMut::new();
+            buf.put_i64_le(3);
+            buf.put_i32_le(1);
+            buf.put_i32_le(count(0));
+            buf.put_u8(1);
+            buf.put_i32_le(count(1));
+            buf.put_i32_le(42);
+            buf.put_i32_le(0);
+            buf.put_i32_le(1);
+            buf.put_u8(10);
+            buf.put_i64_le(1);
+            buf.put_i64_le(2);
+            buf.put_i32_le(count(2));
+            buf.put_i32_le(0);
+            assert!(CachePartitionsResponse::decode(&buf).is_err());
         }
     }
 }
@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+pub(crate) struct RequestIdGenerator {
+    next: AtomicI64,
+}
+
+impl RequestIdGenerator {
+    pub(crate) fn new() -> Self {
+        RequestIdGenerator {
+            next: AtomicI64::new(0),
+        }
+    }
+
+    // ids start at 0 and wrap back to 0 after i64::MAX, so they are never negative
+    pub(crate) fn next(&self) -> i64 {
+        self.next
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| {
+                Some(if id == i64::MAX { 0 } else { id + 1 })
+            })
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_wraps_to_zero() {
+        let generator = RequestIdGenerator {
+            next: AtomicI64::new(i64::MAX),
+        };
+        assert_eq!(generator.next(), i64::MAX);
+        assert_eq!(generator.next(), 0);
+        assert_eq!(generator.next(), 1);
+    }
+}
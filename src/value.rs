@@ -0,0 +1,451 @@
+use bytes::{BufMut, BytesMut};
+use std::io::{self, Error, ErrorKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    Bool(bool),
+    String(String),
+    Uuid(u128),
+    // milliseconds since the epoch
+    Date(i64),
+    // milliseconds since the epoch and the nanoseconds within that millisecond
+    Timestamp { millis: i64, nanos: i32 },
+    // milliseconds since midnight
+    Time(i64),
+    // unscaled * 10^-scale, values that don't fit an i128 can't be decoded
+    Decimal { unscaled: i128, scale: i32 },
+    Bytes(Vec<u8>),
+}
+
+// the same type-code wire format is used for query args, cache keys and values, and row cells
+pub trait Encode {
+    fn encode(&self, buf: &mut BytesMut) -> io::Result<()>;
+
+    fn length(&self) -> usize;
+}
+
+impl Encode for Value {
+    fn encode(&self, buf: &mut BytesMut) -> io::Result<()> {
+        match self {
+            Value::Null => buf.put_u8(101),
+            Value::I8(v) => {
+                buf.put_u8(1);
+                buf.put_i8(*v);
+            }
+            Value::I16(v) => {
+                buf.put_u8(2);
+                buf.put_i16_le(*v);
+            }
+            Value::I32(v) => {
+                buf.put_u8(3);
+                buf.put_i32_le(*v);
+            }
+            Value::I64(v) => {
+                buf.put_u8(4);
+                buf.put_i64_le(*v);
+            }
+            Value::F32(v) => {
+                buf.put_u8(5);
+                buf.put_f32_le(*v);
+            }
+            Value::F64(v) => {
+                buf.put_u8(6);
+                buf.put_f64_le(*v);
+            }
+            Value::Char(v) => {
+                // a java char is a single UTF-16 code unit, chars outside the BMP can't be sent
+                let mut units = [0u16; 2];
+                let [unit] = v.encode_utf16(&mut units) else {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Char {:?} is outside the Basic Multilingual Plane", v),
+                    ));
+                };
+                buf.put_u8(7);
+                buf.put_u16_le(*unit);
+            }
+            Value::Bool(v) => {
+                buf.put_u8(8);
+                buf.put_u8(*v as u8);
+            }
+            Value::String(v) => {
+                buf.put_u8(9);
+                buf.put_i32_le(v.len() as i32);
+                buf.extend_from_slice(v.as_bytes());
+            }
+            Value::Uuid(v) => {
+                buf.put_u8(10);
+                buf.put_u64_le((*v >> 64) as u64);
+                buf.put_u64_le(*v as u64);
+            }
+            Value::Date(v) => {
+                buf.put_u8(11);
+                buf.put_i64_le(*v);
+            }
+            Value::Bytes(v) => {
+                buf.put_u8(12);
+                buf.put_i32_le(v.len() as i32);
+                buf.extend_from_slice(v);
+            }
+            Value::Decimal { unscaled, scale } => {
+                let magnitude = decimal_magnitude(*unscaled);
+                buf.put_u8(30);
+                buf.put_i32_le(*scale);
+                buf.put_i32_le(magnitude.len() as i32);
+                buf.extend_from_slice(&magnitude);
+            }
+            Value::Timestamp { millis, nanos } => {
+                buf.put_u8(33);
+                buf.put_i64_le(*millis);
+                buf.put_i32_le(*nanos);
+            }
+            Value::Time(v) => {
+                buf.put_u8(36);
+                buf.put_i64_le(*v);
+            }
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        1 + match self {
+            Value::Null => 0,
+            Value::I8(_) | Value::Bool(_) => 1,
+            Value::I16(_) | Value::Char(_) => 2,
+            Value::I32(_) | Value::F32(_) => 4,
+            Value::I64(_) | Value::F64(_) | Value::Date(_) | Value::Time(_) => 8,
+            Value::Timestamp { .. } => 12,
+            Value::Uuid(_) => 16,
+            Value::String(v) => 4 + v.len(),
+            Value::Bytes(v) => 4 + v.len(),
+            Value::Decimal { unscaled, .. } => 4 + 4 + decimal_magnitude(*unscaled).len(),
+        }
+    }
+}
+
+impl Value {
+    // decodes the value at offset, returning it with the number of bytes consumed
+    pub fn decode(data: &[u8], start: usize) -> io::Result<(Value, usize)> {
+        let mut offset = start;
+        let type_code = read_bytes(data, &mut offset, 1)?[0];
+        let value = match type_code {
+            1 => Value::I8(read_bytes(data, &mut offset, 1)?[0] as i8),
+            2 => Value::I16(i16::from_le_bytes(
+                read_bytes(data, &mut offset, 2)?.try_into().unwrap(),
+            )),
+            3 => Value::I32(i32::from_le_bytes(
+                read_bytes(data, &mut offset, 4)?.try_into().unwrap(),
+            )),
+            4 => Value::I64(i64::from_le_bytes(
+                read_bytes(data, &mut offset, 8)?.try_into().unwrap(),
+            )),
+            5 => Value::F32(f32::from_le_bytes(
+                read_bytes(data, &mut offset, 4)?.try_into().unwrap(),
+            )),
+            6 => Value::F64(f64::from_le_bytes(
+                read_bytes(data, &mut offset, 8)?.try_into().unwrap(),
+            )),
+            7 => {
+                let unit =
+                    u16::from_le_bytes(read_bytes(data, &mut offset, 2)?.try_into().unwrap());
+                let c = char::from_u32(unit as u32).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unpaired surrogate {:#06x} in char value", unit),
+                    )
+                })?;
+                Value::Char(c)
+            }
+            8 => Value::Bool(read_bytes(data, &mut offset, 1)?[0] != 0),
+            9 => {
+                let length =
+                    i32::from_le_bytes(read_bytes(data, &mut offset, 4)?.try_into().unwrap());
+                let bytes = read_bytes(data, &mut offset, length.max(0) as usize)?;
+                let s = String::from_utf8(bytes.to_vec())
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                Value::String(s)
+            }
+            10 => {
+                let most_significant = read_i64(data, &mut offset)? as u64;
+                let least_significant = read_i64(data, &mut offset)? as u64;
+                Value::Uuid(((most_significant as u128) << 64) | least_significant as u128)
+            }
+            11 => Value::Date(read_i64(data, &mut offset)?),
+            12 => {
+                let length = read_i32(data, &mut offset)?;
+                Value::Bytes(read_bytes(data, &mut offset, length.max(0) as usize)?.to_vec())
+            }
+            30 => {
+                let scale = read_i32(data, &mut offset)?;
+                let length = read_i32(data, &mut offset)?;
+                let magnitude = read_bytes(data, &mut offset, length.max(0) as usize)?;
+                Value::Decimal {
+                    unscaled: decode_decimal_magnitude(magnitude)?,
+                    scale,
+                }
+            }
+            33 => Value::Timestamp {
+                millis: read_i64(data, &mut offset)?,
+                nanos: read_i32(data, &mut offset)?,
+            },
+            36 => Value::Time(read_i64(data, &mut offset)?),
+            101 => Value::Null,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unsupported type code {}", type_code),
+                ))
+            }
+        };
+        Ok((value, offset - start))
+    }
+}
+
+impl From<i8> for Value {
+    fn from(v: i8) -> Self {
+        Value::I8(v)
+    }
+}
+
+impl From<i16> for Value {
+    fn from(v: i16) -> Self {
+        Value::I16(v)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value::I32(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::I64(v)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Self {
+        Value::F32(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::F64(v)
+    }
+}
+
+impl From<char> for Value {
+    fn from(v: char) -> Self {
+        Value::Char(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::String(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::String(v.to_string())
+    }
+}
+
+// None maps to Value::Null, which lets nullable columns be bound directly
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+// the big-endian magnitude of a java BigDecimal's unscaled value with the sign in the top bit
+fn decimal_magnitude(unscaled: i128) -> Vec<u8> {
+    let bytes = unscaled.unsigned_abs().to_be_bytes();
+    let first = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    let mut magnitude = bytes[first..].to_vec();
+    if magnitude.first().is_none_or(|b| b & 0x80 != 0) {
+        magnitude.insert(0, 0);
+    }
+    if unscaled < 0 {
+        magnitude[0] |= 0x80;
+    }
+    magnitude
+}
+
+fn decode_decimal_magnitude(magnitude: &[u8]) -> io::Result<i128> {
+    let out_of_range = || Error::new(ErrorKind::InvalidData, "Decimal does not fit in an i128");
+    let negative = magnitude.first().is_some_and(|b| b & 0x80 != 0);
+    let mut value: u128 = 0;
+    for (i, b) in magnitude.iter().enumerate() {
+        let b = if i == 0 { b & 0x7f } else { *b };
+        if value >> 120 != 0 {
+            return Err(out_of_range());
+        }
+        value = (value << 8) | b as u128;
+    }
+    if negative {
+        0i128.checked_sub_unsigned(value).ok_or_else(out_of_range)
+    } else {
+        i128::try_from(value).map_err(|_| out_of_range())
+    }
+}
+
+fn read_i32(data: &[u8], offset: &mut usize) -> io::Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(data, offset, 4)?.try_into().unwrap()))
+}
+
+fn read_i64(data: &[u8], offset: &mut usize) -> io::Result<i64> {
+    Ok(i64::from_le_bytes(read_bytes(data, offset, 8)?.try_into().unwrap()))
+}
+
+pub(crate) fn read_bytes<'a>(
+    data: &'a [u8],
+    offset: &mut usize,
+    length: usize,
+) -> io::Result<&'a [u8]> {
+    if *offset + length > data.len() {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated value"));
+    }
+    let bytes = &data[*offset..(*offset + length)];
+    *offset += length;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: &Value) -> Value {
+        let mut buf = BytesMut::new();
+        value.encode(&mut buf).unwrap();
+        let (decoded, consumed) = Value::decode(&buf, 0).unwrap();
+        assert_eq!(consumed, buf.len());
+        decoded
+    }
+
+    #[test]
+    fn test_f64_round_trip_bits() {
+        for v in [f64::NAN, -0.0, f64::INFINITY, f64::NEG_INFINITY, 1.5] {
+            match round_trip(&Value::F64(v)) {
+                Value::F64(decoded) => assert_eq!(decoded.to_bits(), v.to_bits()),
+                other => panic!("unexpected value {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_f32_round_trip_bits() {
+        for v in [f32::NAN, -0.0, f32::INFINITY, f32::NEG_INFINITY, 1.5] {
+            match round_trip(&Value::F32(v)) {
+                Value::F32(decoded) => assert_eq!(decoded.to_bits(), v.to_bits()),
+                other => panic!("unexpected value {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_native_types() {
+        assert_eq!(Value::from(5), Value::I32(5));
+        assert_eq!(Value::from(5i64), Value::I64(5));
+        assert_eq!(Value::from(1.5), Value::F64(1.5));
+        assert_eq!(Value::from(true), Value::Bool(true));
+        assert_eq!(Value::from("foo"), Value::String("foo".to_string()));
+        assert_eq!(Value::from("foo".to_string()), Value::String("foo".to_string()));
+        assert_eq!(Value::from(Some(5)), Value::I32(5));
+        assert_eq!(Value::from(None::<&str>), Value::Null);
+    }
+
+    #[test]
+    fn test_temporal_and_binary_round_trip() {
+        for value in [
+            Value::Uuid(0x0123456789abcdef_fedcba9876543210),
+            Value::Date(1_700_000_000_000),
+            Value::Timestamp {
+                millis: 1_700_000_000_123,
+                nanos: 456_789,
+            },
+            Value::Time(12 * 3600 * 1000),
+            Value::Bytes(vec![1, 2, 3]),
+        ] {
+            assert_eq!(round_trip(&value), value);
+        }
+    }
+
+    #[test]
+    fn test_decimal_round_trip() {
+        for unscaled in [0, 1, -1, 127, 128, -128, 12345, i128::MAX, i128::MIN] {
+            let value = Value::Decimal { unscaled, scale: 2 };
+            assert_eq!(round_trip(&value), value);
+        }
+        // -128 as a java BigDecimal: magnitude 0x00 0x80 with the sign bit set
+        let mut buf = BytesMut::new();
+        Value::Decimal {
+            unscaled: -128,
+            scale: 0,
+        }
+        .encode(&mut buf)
+        .unwrap();
+        assert_eq!(&buf[..], &[30, 0, 0, 0, 0, 2, 0, 0, 0, 0x80, 0x80]);
+    }
+
+    #[test]
+    fn test_decimal_out_of_range() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(30);
+        buf.put_i32_le(0);
+        buf.put_i32_le(17);
+        buf.put_u8(1);
+        buf.extend_from_slice(&[0; 16]);
+        assert!(Value::decode(&buf, 0).is_err());
+    }
+
+    #[test]
+    fn test_char_outside_bmp() {
+        let mut buf = BytesMut::new();
+        let result = Value::Char('\u{1f600}').encode(&mut buf);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_char_round_trip() {
+        assert_eq!(round_trip(&Value::Char('a')), Value::Char('a'));
+        assert_eq!(round_trip(&Value::Char('\u{03bb}')), Value::Char('\u{03bb}'));
+    }
+
+    #[test]
+    fn test_decode_consumed_at_offset() {
+        let mut buf = BytesMut::new();
+        Value::I32(7).encode(&mut buf).unwrap();
+        Value::String("ab".to_string()).encode(&mut buf).unwrap();
+        assert_eq!(Value::decode(&buf, 0).unwrap(), (Value::I32(7), 5));
+        assert_eq!(
+            Value::decode(&buf, 5).unwrap(),
+            (Value::String("ab".to_string()), 7)
+        );
+    }
+
+    #[test]
+    fn test_char_unpaired_surrogate() {
+        let data = [7, 0x00, 0xd8];
+        assert!(Value::decode(&data, 0).is_err());
+    }
+}